@@ -37,6 +37,31 @@ pub mod mem {
         )
     }
 
+    pub fn map_range(
+        root: &mut crate::arch::isa::page::Table,
+        vaddr: usize,
+        paddr: usize,
+        size: usize,
+        bits: crate::page::PageBits,
+    ) {
+        crate::arch::isa::page::map_range(
+            root,
+            vaddr,
+            paddr,
+            size,
+            crate::arch::isa::page::PageEntryBits::from(bits).val(),
+        )
+    }
+
+    /// Build the value of the address translation register for `root`
+    pub fn build_satp(root: &crate::arch::isa::page::Table, asid: usize) -> usize {
+        crate::arch::isa::page::build_satp(
+            crate::arch::isa::page::SatpMode::Sv39,
+            asid,
+            root as *const crate::arch::isa::page::Table as usize,
+        )
+    }
+
     pub fn unmap(root: &mut crate::arch::isa::page::Table) {
         crate::arch::isa::page::unmap(root)
     }
@@ -53,6 +78,14 @@ pub mod mem {
         crate::arch::isa::page::dealloc(ptr)
     }
 
+    pub fn is_allocation(ptr: *mut u8) -> bool {
+        crate::arch::isa::page::is_allocation(ptr)
+    }
+
+    pub fn descriptors() -> (*mut u8, usize) {
+        crate::arch::isa::page::descriptors()
+    }
+
     pub fn page_count() -> usize {
         crate::arch::isa::page::page_count()
     }
@@ -116,6 +149,11 @@ pub mod kmem {
         fn get_size(&self) -> usize {
             self.flags_size & !AllocFlags::Taken.val()
         }
+
+        /// The allocation right after this one
+        unsafe fn next(&self) -> *mut Allocation {
+            (self as *const Allocation as *mut u8).add(self.get_size()) as *mut Allocation
+        }
     }
 
     impl fmt::Display for Allocation {
@@ -124,9 +162,13 @@ pub mod kmem {
         }
     }
 
+    /// Alignment of every heap allocation
+    const MIN_ALIGN: usize = 8;
+
     static mut KMEM_HEAD: *mut Allocation = null_mut();
     static mut KMEM_ALLOC: usize = 0;
     static mut KMEM_PAGE_TABLE: *mut crate::arch::isa::page::Table = null_mut();
+    static mut KERNEL_SATP: usize = 0;
 
     // TODO: Add safety doc
     #[allow(clippy::missing_safety_doc)]
@@ -138,6 +180,12 @@ pub mod kmem {
         unsafe { KMEM_ALLOC }
     }
 
+    /// End of the heap, right after its last allocation
+    unsafe fn get_tail() -> *mut Allocation {
+        (KMEM_HEAD as *mut u8).add(KMEM_ALLOC * crate::arch::isa::page::PAGE_SIZE)
+            as *mut Allocation
+    }
+
     pub fn init() {
         unsafe {
             KMEM_ALLOC = 2048;
@@ -151,109 +199,339 @@ pub mod kmem {
             crate::dbg!(KMEM_PAGE_TABLE);
             crate::dbg!(KMEM_HEAD);
 
-            init_identity_map()
+            init_identity_map();
+            assert!(verify_kernel_map(), "kernel page table is not sound");
+
+            // only translates supervisor mode, see `satp`
+            KERNEL_SATP = crate::arch::mem::build_satp(&*KMEM_PAGE_TABLE, 0);
+            crate::arch::isa::encoding::write_satp(KERNEL_SATP);
+            crate::arch::mem::flush_hw_cache(0);
         }
     }
 
-    fn init_identity_map() {
-        unsafe {
-            let num_pages = crate::consts::HEAP_START
-                + crate::consts::HEAP_SIZE / crate::arch::isa::page::PAGE_SIZE;
+    /// Regions the kernel maps onto themselves, with the permissions each
+    /// one gets
+    fn kernel_regions() -> [(&'static str, usize, usize, crate::page::PageBits); 9] {
+        use crate::consts::*;
+        use crate::page::PageBits;
 
-            crate::arch::isa::page::ident_map_range(
-                &mut *KMEM_PAGE_TABLE,
-                crate::arch::kmem::get_head() as usize,
-                crate::arch::kmem::get_head() as usize
-                    + crate::arch::kmem::get_num_allocations() * 4096,
-                crate::arch::isa::page::PageEntryBits::from(crate::page::PageBits::ReadWrite).val(),
-            );
+        unsafe {
+            [
+                ("text", TEXT_START, TEXT_END, PageBits::ReadExecute),
+                ("rodata", RODATA_START, RODATA_END, PageBits::Read),
+                ("data", DATA_START, DATA_END, PageBits::ReadWrite),
+                ("bss", BSS_START, BSS_END, PageBits::ReadWrite),
+                ("stack", STACK_START, STACK_END, PageBits::ReadWrite),
+                (
+                    "heap",
+                    HEAP_START,
+                    HEAP_START + HEAP_SIZE,
+                    PageBits::ReadWrite,
+                ),
+                (
+                    "uart",
+                    UART_ADDRESS,
+                    UART_ADDRESS + UART_SIZE,
+                    PageBits::ReadWrite,
+                ),
+                (
+                    "clint",
+                    CLINT_ADDRESS,
+                    CLINT_ADDRESS + CLINT_SIZE,
+                    PageBits::ReadWrite,
+                ),
+                (
+                    "test",
+                    TEST_DEVICE_ADDRESS,
+                    TEST_DEVICE_ADDRESS + TEST_DEVICE_SIZE,
+                    PageBits::ReadWrite,
+                ),
+            ]
+        }
+    }
 
+    fn init_identity_map() {
+        for &(_, start, end, bits) in kernel_regions().iter() {
             crate::arch::isa::page::ident_map_range(
-                &mut *KMEM_PAGE_TABLE,
-                crate::consts::HEAP_START,
-                crate::consts::HEAP_START + num_pages,
-                crate::arch::isa::page::PageEntryBits::from(crate::page::PageBits::ReadWrite).val(),
+                unsafe { &mut *KMEM_PAGE_TABLE },
+                start,
+                end,
+                crate::arch::isa::page::PageEntryBits::from(bits).val(),
             );
+        }
+    }
 
-            crate::arch::isa::page::ident_map_range(
-                &mut *KMEM_PAGE_TABLE,
-                crate::consts::TEXT_START,
-                crate::consts::TEXT_END,
-                crate::arch::isa::page::PageEntryBits::from(crate::page::PageBits::ReadExecute)
-                    .val(),
-            );
+    /// Address translation value of the kernel page table, 0 while paging
+    /// is off
+    ///
+    /// The trap handler and everything else running in machine mode ignore
+    /// satp and access memory untranslated. Only supervisor mode, which
+    /// kernel threads and the idle task run in, is held to the kernel page
+    /// table and its permissions.
+    pub fn satp() -> usize {
+        unsafe { KERNEL_SATP }
+    }
 
-            crate::arch::isa::page::ident_map_range(
-                &mut *KMEM_PAGE_TABLE,
-                crate::consts::RODATA_START,
-                crate::consts::RODATA_END,
-                crate::arch::isa::page::PageEntryBits::from(crate::page::PageBits::ReadExecute)
-                    .val(),
-            );
+    /// Checks every page of the kernel regions is mapped with exactly the
+    /// permissions of its region and none for user mode, printing the pages
+    /// that are not
+    pub fn verify_kernel_map() -> bool {
+        use crate::arch::isa::page::{leaf_entry, PageEntryBits, PAGE_SIZE};
 
-            crate::arch::isa::page::ident_map_range(
-                &mut *KMEM_PAGE_TABLE,
-                crate::consts::DATA_START,
-                crate::consts::DATA_END,
-                crate::arch::isa::page::PageEntryBits::from(crate::page::PageBits::ReadWrite).val(),
-            );
+        if unsafe { KMEM_PAGE_TABLE.is_null() } {
+            return false;
+        }
 
-            crate::arch::isa::page::ident_map_range(
-                &mut *KMEM_PAGE_TABLE,
-                crate::consts::BSS_START,
-                crate::consts::BSS_END,
-                crate::arch::isa::page::PageEntryBits::from(crate::page::PageBits::ReadWrite).val(),
-            );
+        let root = unsafe { &*KMEM_PAGE_TABLE };
+        let rwxu = PageEntryBits::ReadWriteExecute.val() | PageEntryBits::User.val();
+        let mut ok = true;
+
+        for &(name, start, end, bits) in kernel_regions().iter() {
+            let expected = PageEntryBits::from(bits).val();
+            let mut page = start & !(PAGE_SIZE - 1);
+
+            while page < end {
+                match leaf_entry(root, page) {
+                    Some(entry) if entry & rwxu == expected => {}
+                    Some(entry) => {
+                        crate::println!(
+                            "kmem: {} page {:#x} has bits {:#x}, expected {:#x}",
+                            name,
+                            page,
+                            entry & rwxu,
+                            expected
+                        );
+                        ok = false;
+                    }
+                    None => {
+                        crate::println!("kmem: {} page {:#x} is not mapped", name, page);
+                        ok = false;
+                    }
+                }
+                page += PAGE_SIZE;
+            }
         }
+
+        ok
     }
 
-    pub fn kzmalloc(size: usize) -> *mut u8 {
-        let size = crate::arch::isa::page::align_val(size, 3);
-        let ret = kmalloc(size);
+    /// Why `kmalloc_aligned` could not allocate
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum KmemError {
+        /// The alignment is not a power of two
+        BadAlignment(usize),
+        /// The alignment is larger than a page, which neither the heap nor
+        /// the page allocator guarantee
+        UnsupportedAlignment(usize),
+        OutOfMemory,
+    }
 
-        if !ret.is_null() {
-            for i in 0..size {
-                unsafe {
-                    (*ret.add(i)) = 0;
+    impl fmt::Display for KmemError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                KmemError::BadAlignment(align) => {
+                    write!(f, "alignment {} is not a power of two", align)
                 }
+                KmemError::UnsupportedAlignment(align) => write!(
+                    f,
+                    "alignment {} is larger than a page ({} bytes)",
+                    align,
+                    crate::arch::isa::page::PAGE_SIZE
+                ),
+                KmemError::OutOfMemory => write!(f, "out of memory"),
             }
         }
+    }
 
-        ret
+    pub fn kzmalloc(size: usize) -> *mut u8 {
+        kzmalloc_aligned(size, MIN_ALIGN).unwrap_or(null_mut())
     }
 
     pub fn kmalloc(size: usize) -> *mut u8 {
-        unsafe {
-            let size = crate::arch::isa::page::align_val(size, 3) + size_of::<Allocation>();
+        kmalloc_aligned(size, MIN_ALIGN).unwrap_or(null_mut())
+    }
 
-            let mut head = KMEM_HEAD;
-            let tail = (KMEM_HEAD as *mut u8).add(KMEM_ALLOC * crate::arch::isa::page::PAGE_SIZE)
-                as *mut Allocation;
+    /// Allocates `size` zeroed bytes aligned to `align`, see `kmalloc_aligned`
+    pub fn kzmalloc_aligned(size: usize, align: usize) -> Result<*mut u8, KmemError> {
+        let ret = kmalloc_aligned(size, align)?;
 
-            while head < tail {
-                if (*head).is_free() && size <= (*head).get_size() {
-                    let chunk_size = (*head).get_size();
-                    let rem = chunk_size - size;
+        unsafe { core::ptr::write_bytes(ret, 0, size) };
+        Ok(ret)
+    }
 
-                    (*head).set_taken();
+    /// Allocates `size` bytes aligned to `align`, a power of two
+    ///
+    /// Page-aligned requests are served by the page allocator, smaller
+    /// alignments by the heap. Alignments larger than a page cannot be
+    /// honoured. Either way the memory is freed with `kfree`.
+    pub fn kmalloc_aligned(size: usize, align: usize) -> Result<*mut u8, KmemError> {
+        let page_size = crate::arch::isa::page::PAGE_SIZE;
 
-                    if rem > size_of::<Allocation>() {
-                        let next = (head as *mut u8).add(size) as *mut Allocation;
+        if !align.is_power_of_two() {
+            return Err(KmemError::BadAlignment(align));
+        }
+        if align > page_size {
+            return Err(KmemError::UnsupportedAlignment(align));
+        }
 
-                        (*next).set_free();
-                        (*next).set_size(rem);
-                        (*head).set_size(size);
-                    } else {
-                        (*head).set_size(chunk_size);
-                    }
-                    return head.add(1) as *mut u8;
+        let ret = if align == page_size {
+            let pages = (size.max(1) + page_size - 1) / page_size;
+            crate::arch::mem::alloc(pages)
+        } else {
+            unsafe { heap_alloc(size, align.max(MIN_ALIGN)) }
+        };
+
+        if ret.is_null() {
+            Err(KmemError::OutOfMemory)
+        } else {
+            Ok(ret)
+        }
+    }
+
+    /// Carves `size` bytes aligned to `align` out of the first free
+    /// allocation that can hold them
+    ///
+    /// When the aligned address is not right after the allocation's header,
+    /// the bytes before it stay behind as a free allocation of their own.
+    unsafe fn heap_alloc(size: usize, align: usize) -> *mut u8 {
+        let header = size_of::<Allocation>();
+        let size = crate::arch::isa::page::align_val(size, 3) + header;
+
+        let mut head = KMEM_HEAD;
+        let tail = get_tail();
+
+        while head < tail {
+            let start = head as usize + header;
+            // headers and sizes are 8-byte aligned, so a non-zero gap always
+            // has room for the leading allocation's header
+            let gap = ((start + align - 1) & !(align - 1)) - start;
+
+            if (*head).is_free() && gap + size <= (*head).get_size() {
+                let mut chunk = head;
+                if gap > 0 {
+                    chunk = (head as *mut u8).add(gap) as *mut Allocation;
+                    (*chunk).set_free();
+                    (*chunk).set_size((*head).get_size() - gap);
+                    (*head).set_size(gap);
+                }
+
+                let chunk_size = (*chunk).get_size();
+                let rem = chunk_size - size;
+
+                (*chunk).set_taken();
+
+                if rem > header {
+                    let next = (chunk as *mut u8).add(size) as *mut Allocation;
+
+                    (*next).set_free();
+                    (*next).set_size(rem);
+                    (*chunk).set_size(size);
                 } else {
-                    head = (head as *mut u8).add((*head).get_size()) as *mut Allocation;
+                    (*chunk).set_size(chunk_size);
                 }
+                return chunk.add(1) as *mut u8;
+            } else {
+                head = (*head).next();
             }
         }
         null_mut()
     }
+
+    /// Frees memory returned by `kmalloc`, `kzmalloc` or their aligned
+    /// variants
+    ///
+    /// The freed allocation is merged with its free neighbours so the heap
+    /// does not fragment into chunks too small to reuse. Pointers outside the
+    /// heap that start a page allocation go back to the page allocator. Null
+    /// pointers, allocations that are already free, pointers into the middle
+    /// of an allocation and any other pointer are ignored.
+    pub fn kfree(ptr: *mut u8) {
+        unsafe {
+            if ptr.is_null() {
+                return;
+            }
+
+            let alloc = (ptr as *mut Allocation).sub(1);
+            if alloc < KMEM_HEAD || alloc >= get_tail() {
+                if crate::arch::mem::is_allocation(ptr) {
+                    crate::arch::mem::dealloc(ptr);
+                }
+                return;
+            }
+            if !is_header(alloc) || (*alloc).is_free() {
+                return;
+            }
+
+            (*alloc).set_free();
+            coalesce(alloc);
+        }
+    }
+
+    /// Whether `alloc` is the header of an allocation rather than a pointer
+    /// into one, found by walking the allocations from the head
+    unsafe fn is_header(alloc: *mut Allocation) -> bool {
+        let tail = get_tail();
+
+        let mut head = KMEM_HEAD;
+        while head < alloc && head < tail {
+            let next = (*head).next();
+            if next <= head {
+                // a zero-sized allocation, the chain is broken
+                return false;
+            }
+            head = next;
+        }
+        head == alloc
+    }
+
+    /// Merges the free allocation `alloc` with the allocations around it
+    /// that are free too
+    unsafe fn coalesce(alloc: *mut Allocation) {
+        let tail = get_tail();
+
+        let next = (*alloc).next();
+        if next < tail && (*next).is_free() {
+            (*alloc).set_size((*alloc).get_size() + (*next).get_size());
+        }
+
+        // allocations only know their size, walk from the head to find the
+        // one before `alloc`
+        let mut prev = KMEM_HEAD;
+        while prev < alloc {
+            let next = (*prev).next();
+            if next == alloc {
+                if (*prev).is_free() {
+                    (*prev).set_size((*prev).get_size() + (*alloc).get_size());
+                }
+                return;
+            }
+            if next <= prev {
+                // a zero-sized allocation, the chain is broken
+                return;
+            }
+            prev = next;
+        }
+    }
+
+    /// Number of bytes in free allocations, headers excluded
+    pub fn free_bytes() -> usize {
+        let mut free = 0;
+
+        unsafe {
+            let mut head = KMEM_HEAD;
+            let tail = get_tail();
+            while !head.is_null() && head < tail {
+                if (*head).is_free() {
+                    free += (*head).get_size().saturating_sub(size_of::<Allocation>());
+                }
+                if (*head).get_size() == 0 {
+                    break;
+                }
+                head = (*head).next();
+            }
+        }
+        free
+    }
 }
 
 pub mod frame {
@@ -267,8 +545,24 @@ pub mod frame {
         sp: usize,
         ra: usize,
         pid: usize,
+        satp: usize,
     ) {
-        crate::arch::isa::process::create_process(frame, pc, sp, ra, pid)
+        crate::arch::isa::process::create_process(frame, pc, sp, ra, pid, satp)
+    }
+
+    /// Sets up `frame` to run `pc` in supervisor mode, in the kernel address
+    /// space once `kmem::init` enabled it
+    pub fn create_kernel_task(
+        frame: &mut crate::arch::isa::trap::TrapFrame,
+        pc: usize,
+        sp: usize,
+        pid: usize,
+    ) {
+        crate::arch::isa::process::create_kernel_task(frame, pc, sp, pid, crate::arch::kmem::satp())
+    }
+
+    pub fn mode_of(frame: usize) -> crate::consts::SwitchMode {
+        crate::arch::isa::switch::mode_of(frame)
     }
 }
 
@@ -95,8 +95,12 @@ impl Exception {
             1 => Exception::InstructionAccessFault,
             2 => Exception::IllegaInstruction,
             5 => Exception::LoadAccessFault,
+            8 => Exception::EnvCallFromUMode,
             9 => Exception::EnvCallFromSMode,
             11 => Exception::EnvCallFromMMode,
+            12 => Exception::InstructionPageFault,
+            13 => Exception::LoadPageFault,
+            15 => Exception::StoreAMOPageFault,
             _ => panic!("Exception '{}' not supported", value),
         }
     }
@@ -144,6 +148,12 @@ pub fn write_mie(val: usize) {
     }
 }
 
+pub fn write_satp(val: usize) {
+    unsafe {
+        asm!("csrw satp, {}", in(reg)val);
+    }
+}
+
 pub fn write_mscratch(val: usize) {
     unsafe {
         asm!("csrw mscratch, {}", in(reg)val);
@@ -4,7 +4,7 @@ use crate::exit::{Exit, ExitCode};
 const SUCCESS: u32 = 0x55_55;
 const FAIL: u32 = 0x33_33;
 const RESET: u32 = 0x77_77;
-const EXIT_ADDRESS: usize = 0x10_0000;
+const EXIT_ADDRESS: usize = crate::consts::TEST_DEVICE_ADDRESS;
 
 #[allow(dead_code)]
 pub struct RISCVExit {
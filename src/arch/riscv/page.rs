@@ -21,12 +21,10 @@ const PAGE_ORDER: usize = 12;
 /// A single page is is 4,096 bytes
 pub const PAGE_SIZE: usize = 1 << PAGE_ORDER;
 
-#[repr(u8)]
-enum PageBits {
-    Empty = 0,
-    Taken = 1 << 0,
-    Last = 1 << 1,
-}
+/// Largest block the allocator hands out or merges into, 2^15 pages
+const MAX_ORDER: usize = 15;
+/// Marks the end of a free list
+const NIL: u32 = u32::MAX;
 
 pub enum SatpMode {
     Off = 0,
@@ -34,53 +32,208 @@ pub enum SatpMode {
     Sv48 = 9,
 }
 
-impl PageBits {
-    fn val(self) -> u8 {
-        match self {
-            PageBits::Empty => 0,
-            PageBits::Taken => 1 << 0,
-            PageBits::Last => 1 << 1,
-        }
-    }
-}
-
 /// Align value to order
 pub const fn align_val(val: usize, order: usize) -> usize {
     let o = (1usize << order) - 1;
     (val + o) & !o
 }
 
+#[repr(u8)]
+enum PageBits {
+    Empty = 0,
+    /// Part of an allocation
+    Taken = 1 << 0,
+    /// Last page of an allocation
+    Last = 1 << 1,
+    /// First page of an allocation
+    First = 1 << 2,
+    /// First page of a free block, linked in its order's free list
+    Free = 1 << 3,
+}
+
+/// The order of a free block is kept in the high bits of its first page's
+/// flags
+const ORDER_SHIFT: u8 = 4;
+
+impl PageBits {
+    fn val(self) -> u8 {
+        self as u8
+    }
+}
+
 /// RISC-V page representation
+///
+/// Every page of an allocation is taken, its first and last pages are marked
+/// as such. A free block is only marked on its first page, along with its
+/// order, the other pages stay empty.
 #[derive(Debug, Clone, Copy)]
 struct Page {
     flags: u8,
 }
 
-/// Initialize the page system
 impl Page {
+    fn is_taken(&self) -> bool {
+        self.flags & PageBits::Taken.val() != 0
+    }
+
+    fn is_first(&self) -> bool {
+        self.flags & PageBits::First.val() != 0
+    }
+
     fn is_last(&self) -> bool {
         self.flags & PageBits::Last.val() != 0
     }
 
-    fn is_taken(&self) -> bool {
-        self.flags & PageBits::Taken.val() != 0
+    /// Order of the free block starting at this page
+    fn order(&self) -> usize {
+        (self.flags >> ORDER_SHIFT) as usize
+    }
+
+    /// Whether a free block of `order` starts at this page
+    fn is_free_block(&self, order: usize) -> bool {
+        self.flags & PageBits::Free.val() != 0 && self.order() == order
+    }
+
+    fn set_free(&mut self, order: usize) {
+        self.flags = PageBits::Free.val() | (order as u8) << ORDER_SHIFT;
     }
 
-    fn is_free(&self) -> bool {
-        !self.is_taken()
+    fn set_flag(&mut self, flag: PageBits) {
+        self.flags |= flag.val();
     }
 
     fn clear(&mut self) {
         self.flags = PageBits::Empty.val();
     }
+}
+
+/// Neighbours of a free block in its free list, as page indexes
+///
+/// The links are kept at the start of the free block itself, which nobody
+/// else uses, so the page descriptors stay a byte each.
+#[repr(C)]
+struct Links {
+    next: u32,
+    prev: u32,
+}
+
+/// Buddy allocator state, kept at `HEAP_START` right before the page
+/// descriptors
+///
+/// Free blocks of `2^order` pages start at a page index that is a multiple
+/// of their size and are linked in `free[order]`. A block and its buddy, the
+/// other half of the block they were split from, merge back as soon as both
+/// are free.
+#[repr(C)]
+struct Buddy {
+    free: [u32; MAX_ORDER + 1],
+    /// Pages handed out, see `page_count`
+    allocated: usize,
+    /// Pages that can be allocated, from `ALLOC_START`
+    usable: usize,
+}
+
+fn buddy() -> *mut Buddy {
+    unsafe { HEAP_START as *mut Buddy }
+}
+
+/// Descriptor of the page at index `i` from `ALLOC_START`
+fn page(i: usize) -> *mut Page {
+    unsafe { ((HEAP_START + size_of::<Buddy>()) as *mut Page).add(i) }
+}
+
+/// Free list links of the free block starting at page index `i`
+fn links(i: usize) -> *mut Links {
+    unsafe { (ALLOC_START + i * PAGE_SIZE) as *mut Links }
+}
+
+/// Smallest order whose blocks hold `pages`
+fn order_of(pages: usize) -> usize {
+    let mut order = 0;
+    while (1 << order) < pages {
+        order += 1;
+    }
+    order
+}
+
+/// Links the block at `i` in the free list of `order`
+unsafe fn push(i: usize, order: usize) {
+    let b = buddy();
+    let head = (*b).free[order];
+
+    (*page(i)).set_free(order);
+    (*links(i)).next = head;
+    (*links(i)).prev = NIL;
+    if head != NIL {
+        (*links(head as usize)).prev = i as u32;
+    }
+    (*b).free[order] = i as u32;
+}
+
+/// Unlinks the free block at `i` from the free list of `order`
+unsafe fn unlink(i: usize, order: usize) {
+    let (next, prev) = ((*links(i)).next, (*links(i)).prev);
+
+    if prev == NIL {
+        (*buddy()).free[order] = next;
+    } else {
+        (*links(prev as usize)).next = next;
+    }
+    if next != NIL {
+        (*links(next as usize)).prev = prev;
+    }
+    (*page(i)).clear();
+}
+
+/// Takes a free block of `order`, splitting a larger one if needed
+unsafe fn take(order: usize) -> Option<usize> {
+    let b = buddy();
+    let from = (order..=MAX_ORDER).find(|&o| (*b).free[o] != NIL)?;
 
-    fn set_flag(&mut self, flag: u8) {
-        self.flags |= flag;
+    let i = (*b).free[from] as usize;
+    // the links live outside the descriptors, check they lead to a free
+    // block before trusting them
+    if i >= (*b).usable || !(*page(i)).is_free_block(from) {
+        panic!("Free list of order {} is corrupted at page {}", from, i);
+    }
+    unlink(i, from);
+    for o in (order..from).rev() {
+        push(i + (1 << o), o);
+    }
+    Some(i)
+}
+
+/// Returns the block of `order` at `i` to the free lists, merging it with
+/// its buddy for as long as the buddy is free too
+unsafe fn release(mut i: usize, mut order: usize) {
+    let usable = (*buddy()).usable;
+
+    while order < MAX_ORDER {
+        let buddy = i ^ (1 << order);
+        if buddy + (1 << order) > usable || !(*page(buddy)).is_free_block(order) {
+            break;
+        }
+        unlink(buddy, order);
+        i = i.min(buddy);
+        order += 1;
     }
+    push(i, order);
+}
 
-    //fn clear_flag(&mut self, flag: u8) {
-    //    self.flags &= !flag;
-    //}
+/// Returns the pages `start..end` to the free lists, as the largest aligned
+/// blocks that fit
+unsafe fn release_range(mut start: usize, end: usize) {
+    while start < end {
+        let mut order = 0;
+        while order < MAX_ORDER
+            && start % (1 << (order + 1)) == 0
+            && start + (1 << (order + 1)) <= end
+        {
+            order += 1;
+        }
+        release(start, order);
+        start += 1 << order;
+    }
 }
 
 /// Initialize the page system
@@ -89,15 +242,19 @@ fn page_init() {
         let num_pages = crate::dbg!(HEAP_SIZE / PAGE_SIZE);
         crate::dbg!(num_pages);
 
-        let ptr = HEAP_START as *mut Page;
-        crate::dbg!(ptr);
+        let meta = size_of::<Buddy>() + num_pages * size_of::<Page>();
+        ALLOC_START = align_val(HEAP_START + meta, PAGE_ORDER);
+        crate::dbg!(ALLOC_START);
+
+        let b = buddy();
+        (*b).free = [NIL; MAX_ORDER + 1];
+        (*b).allocated = 0;
+        (*b).usable = (HEAP_START + HEAP_SIZE - ALLOC_START) / PAGE_SIZE;
 
         for i in 0..num_pages {
-            (*ptr.add(i)).clear();
+            (*page(i)).clear();
         }
-
-        ALLOC_START = align_val(HEAP_START + num_pages * size_of::<Page>(), PAGE_ORDER);
-        crate::dbg!(ALLOC_START);
+        release_range(0, (*b).usable);
     }
 }
 
@@ -106,88 +263,114 @@ pub fn init() {
     page_init();
 }
 
-/// Get number of total allocated pages
-// TODO: Refactor how pages are counted. Linked list maybe?
-pub fn page_count() -> usize {
+/// Get the allocator state and page descriptors and their length in bytes
+pub fn descriptors() -> (*mut u8, usize) {
     unsafe {
-        crate::dbg!(ALLOC_START);
-        let num_pages = HEAP_SIZE / PAGE_SIZE;
-        let mut beg = HEAP_START as *const Page;
-        let end = beg.add(num_pages);
-
-        let mut count = 0;
-        while beg < end {
-            if (*beg).is_taken() {
-                loop {
-                    count += 1;
-                    if (*beg).is_last() {
-                        break;
-                    }
-                    beg = beg.add(1);
-                }
-            }
-            beg = beg.add(1);
-        }
-        count
+        (
+            HEAP_START as *mut u8,
+            size_of::<Buddy>() + HEAP_SIZE / PAGE_SIZE * size_of::<Page>(),
+        )
     }
 }
 
-/// Get address of the last page
+/// Get number of total allocated pages
+pub fn page_count() -> usize {
+    unsafe { (*buddy()).allocated }
+}
+
+/// Get address of the last allocated page, null if no page is allocated
 pub fn get_last_page() -> *mut u8 {
+    let mut last = null_mut();
+
+    for_each_block(|i, pages, taken| {
+        if taken {
+            last = unsafe { (ALLOC_START + (i + pages - 1) * PAGE_SIZE) as *mut u8 };
+        }
+    });
+    last
+}
+
+/// Calls `f` with the first page index, length and state of every block, in
+/// address order
+fn for_each_block(mut f: impl FnMut(usize, usize, bool)) {
     unsafe {
-        let num_pages = HEAP_SIZE / PAGE_SIZE;
-        let mut beg = HEAP_START as *const Page;
-        let end = beg.add(num_pages);
-
-        while beg < end {
-            if (*beg).is_taken() {
-                loop {
-                    if (*beg).is_last() {
-                        return beg as *mut u8;
-                    }
-                    beg = beg.add(1);
-                }
+        let usable = (*buddy()).usable;
+        let mut i = 0;
+
+        while i < usable {
+            if (*page(i)).is_taken() {
+                let pages = allocation_len(i);
+                f(i, pages, true);
+                i += pages;
+            } else {
+                let pages = 1 << (*page(i)).order();
+                f(i, pages, false);
+                i += pages;
             }
-            beg = beg.add(1);
         }
-        beg as *mut u8
     }
 }
 
+/// Number of pages of the allocation starting at page index `i`
+unsafe fn allocation_len(i: usize) -> usize {
+    let usable = (*buddy()).usable;
+    let mut pages = 1;
+
+    while !(*page(i + pages - 1)).is_last() && i + pages < usable {
+        pages += 1;
+    }
+    pages
+}
+
 /// Allocate N pages in RISC-V
 ///
 /// * `pages`: the number of `PAGE_SIZE` pages to allocate
+///
+/// The pages come from the smallest free block that holds them, the pages
+/// of the block past the allocation go back to the free lists.
 pub fn alloc(pages: usize) -> *mut u8 {
+    if pages == 0 || pages > 1 << MAX_ORDER {
+        return null_mut();
+    }
+    let order = order_of(pages);
+
     unsafe {
-        let num_pages = HEAP_SIZE / PAGE_SIZE;
-        let ptr = HEAP_START as *mut Page;
-
-        for i in 0..=num_pages.checked_sub(pages).unwrap() {
-            let mut found = false;
-
-            if (*ptr.add(i)).is_free() {
-                found = true;
-                for j in i..i + pages {
-                    if (*ptr.add(j)).is_taken() {
-                        found = false;
-                        break;
-                    }
-                }
-            }
+        let i = match take(order) {
+            Some(i) => i,
+            None => return null_mut(),
+        };
+        release_range(i + pages, i + (1 << order));
+
+        for j in i..i + pages {
+            (*page(j)).clear();
+            (*page(j)).set_flag(PageBits::Taken);
+        }
+        (*page(i)).set_flag(PageBits::First);
+        (*page(i + pages - 1)).set_flag(PageBits::Last);
+        (*buddy()).allocated += pages;
 
-            if found {
-                for k in i..i + pages - 1 {
-                    (*ptr.add(k)).set_flag(PageBits::Taken.val());
-                }
-                (*ptr.add(i + pages - 1)).set_flag(PageBits::Taken.val());
-                (*ptr.add(i + pages - 1)).set_flag(PageBits::Last.val());
+        #[cfg(feature = "hardened")]
+        crate::harden::seal_pages();
 
-                return (ALLOC_START + PAGE_SIZE * i) as *mut u8;
-            }
-        }
+        (ALLOC_START + PAGE_SIZE * i) as *mut u8
     }
+}
+
+/// Index of the page `ptr` points to the start of, if it is an allocatable
+/// page
+fn index_of(ptr: *mut u8) -> Option<usize> {
+    let offset = (ptr as usize).checked_sub(unsafe { ALLOC_START })?;
+    let i = offset / PAGE_SIZE;
+
+    if offset % PAGE_SIZE != 0 || i >= unsafe { (*buddy()).usable } {
+        return None;
+    }
+    Some(i)
+}
 
-    null_mut()
+/// Whether `ptr` is the first page of an allocation `alloc` handed out
+pub fn is_allocation(ptr: *mut u8) -> bool {
+    index_of(ptr).map_or(false, |i| unsafe { (*page(i)).is_first() })
 }
 
 /// Dellocates a page in RISC-V
@@ -201,21 +384,20 @@ pub fn dealloc(ptr: *mut u8) {
         panic!("Null pointer")
     }
     unsafe {
-        let addr = HEAP_START + (ptr as usize - ALLOC_START) / PAGE_SIZE;
-        // we are looking for the page structure address
-        let mut page = addr as *mut Page;
-        if !(*page).is_taken() {
-            panic!("Freeing a non-taken page.")
+        let i = match index_of(ptr) {
+            Some(i) if (*page(i)).is_first() => i,
+            _ => panic!("Freeing a non-taken page."),
+        };
+
+        let pages = allocation_len(i);
+        for j in i..i + pages {
+            (*page(j)).clear();
         }
+        (*buddy()).allocated -= pages;
+        release_range(i, i + pages);
 
-        while (*page).is_taken() && !(*page).is_last() {
-            (*page).clear();
-            page = page.add(1);
-        }
-
-        crate::dbg!((*page).is_free());
-        (*page).clear();
-        crate::dbg!((*page).is_free());
+        #[cfg(feature = "hardened")]
+        crate::harden::seal_pages();
     }
 }
 
@@ -241,45 +423,50 @@ pub fn zalloc(pages: usize) -> *mut u8 {
 /// Print the currente page allocation status
 pub fn print_page_allocation() {
     unsafe {
-        let num_pages = HEAP_SIZE / PAGE_SIZE;
-        let mut beginning = HEAP_START as *const Page;
-        let end = beginning.add(num_pages);
-
+        let usable = (*buddy()).usable;
         let alloc_start = ALLOC_START;
-        let alloc_end = ALLOC_START + num_pages * PAGE_SIZE;
+        let alloc_end = ALLOC_START + usable * PAGE_SIZE;
 
         println!("\n------------------------------------");
         println!(
             "Page allocation table:\nMeta: {:p} -> {:p}:\nPhys: 0x{:x} -> 0x{:x}",
-            beginning, end, alloc_start, alloc_end
+            buddy(),
+            page(HEAP_SIZE / PAGE_SIZE),
+            alloc_start,
+            alloc_end
         );
         println!("------------------------------------");
 
-        let mut num = 0;
-        while beginning < end {
-            if (*beginning).is_taken() {
-                let start = beginning as usize;
-                let memaddr = ALLOC_START + (start - HEAP_START) * PAGE_SIZE;
-                crate::print!("0x{:x} => ", memaddr);
-                loop {
-                    num += 1;
-                    if (*beginning).is_last() {
-                        let end = beginning as usize;
-                        let memaddr = ALLOC_START + (end - HEAP_START) * PAGE_SIZE + PAGE_SIZE - 1;
-                        println!("0x{:x}: {:>3} page(s)", memaddr, (end - start + 1));
-                        break;
-                    }
-                    beginning = beginning.add(1);
-                }
+        for_each_block(|i, pages, taken| {
+            if taken {
+                let start = ALLOC_START + i * PAGE_SIZE;
+                println!(
+                    "0x{:x} => 0x{:x}: {:>3} page(s)",
+                    start,
+                    start + pages * PAGE_SIZE - 1,
+                    pages
+                );
             }
-            beginning = beginning.add(1);
-        }
+        });
+
+        let num = page_count();
         println!("Allocated: {} pages ({} bytes)", num, num * PAGE_SIZE);
         println!(
             "Free: {} pages ({} bytes)",
-            num_pages - num,
-            (num_pages - num) * PAGE_SIZE
+            usable - num,
+            (usable - num) * PAGE_SIZE
         );
+        for order in 0..=MAX_ORDER {
+            let mut blocks = 0;
+            let mut i = (*buddy()).free[order];
+            while i != NIL {
+                blocks += 1;
+                i = (*links(i as usize)).next;
+            }
+            if blocks > 0 {
+                println!("Order {:>2}: {} free block(s)", order, blocks);
+            }
+        }
 
         println!("------------------------------------\n");
     }
@@ -303,6 +490,7 @@ pub enum PageEntryBits {
     ReadWriteExecute = 1 << 1 | 1 << 2 | 1 << 3,
 
     // User Convenience Combinations
+    UserRead = 1 << 1 | 1 << 4,
     UserReadWrite = 1 << 1 | 1 << 2 | 1 << 4,
     UserReadExecute = 1 << 1 | 1 << 3 | 1 << 4,
     UserReadWriteExecute = 1 << 1 | 1 << 2 | 1 << 3 | 1 << 4,
@@ -317,13 +505,13 @@ impl PageEntryBits {
 impl core::convert::From<crate::page::PageBits> for PageEntryBits {
     fn from(bits: crate::page::PageBits) -> Self {
         match bits {
-            crate::page::PageBits::UserReadWrite | crate::page::PageBits::ReadWrite => {
-                PageEntryBits::UserReadWrite
-            }
-            crate::page::PageBits::UserReadExecute | crate::page::PageBits::ReadExecute => {
-                PageEntryBits::UserReadExecute
-            }
+            crate::page::PageBits::UserRead => PageEntryBits::UserRead,
+            crate::page::PageBits::UserReadWrite => PageEntryBits::UserReadWrite,
+            crate::page::PageBits::UserReadExecute => PageEntryBits::UserReadExecute,
             crate::page::PageBits::UserReadWriteExecute => PageEntryBits::UserReadWriteExecute,
+            crate::page::PageBits::Read => PageEntryBits::Read,
+            crate::page::PageBits::ReadExecute => PageEntryBits::ReadExecute,
+            crate::page::PageBits::ReadWrite => PageEntryBits::ReadWrite,
         }
     }
 }
@@ -407,7 +595,7 @@ pub fn map(root: &mut Table, vaddr: usize, paddr: usize, bits: usize, level: usi
 
     for i in (level..2).rev() {
         if !refc.is_valid() {
-            let page = zalloc(1);
+            let page = crate::slab::zalloc::<Table>();
             refc.set_entry((page as usize >> 2) | PageEntryBits::Valid.val());
         }
 
@@ -430,7 +618,7 @@ fn dealloc_level(root: &mut Table) {
         let entry = &root.entries[level];
         if entry.is_valid() && entry.is_branch() {
             let memaddr_lv0 = (entry.get_entry() & !0x3ff) << 2;
-            dealloc(memaddr_lv0 as *mut u8);
+            crate::slab::free(memaddr_lv0 as *mut Table);
         }
     }
 }
@@ -446,7 +634,7 @@ pub fn unmap(root: &mut Table) {
             let table_lv1 = unsafe { (memaddr_lv1 as *mut Table).as_mut().unwrap() };
 
             dealloc_level(table_lv1);
-            dealloc(memaddr_lv1 as *mut u8);
+            crate::slab::free(table_lv1 as *mut Table);
         }
     }
 }
@@ -482,6 +670,36 @@ pub fn virt_to_phys(root: Table, vaddr: usize) -> Option<usize> {
     None
 }
 
+/// Leaf entry translating `vaddr`, `None` when it is not mapped
+pub fn leaf_entry(root: &Table, vaddr: usize) -> Option<usize> {
+    let vpn = [
+        // VPN[0] = vaddr[20:12]
+        (vaddr >> 12) & 0x1ff,
+        // VPN[1] = vaddr[29:21]
+        (vaddr >> 21) & 0x1ff,
+        // VPN[2] = vaddr[38:30]
+        (vaddr >> 30) & 0x1ff,
+    ];
+
+    let mut refc = &root.entries[vpn[2]];
+
+    for i in (0..3).rev() {
+        if refc.is_invalid() {
+            return None;
+        } else if refc.is_leaf() {
+            return Some(refc.get_entry());
+        } else if i == 0 {
+            // a branch at the last level is malformed
+            return None;
+        }
+
+        let entry = ((refc.get_entry() & !0x3ff) << 2) as *const PageEntry;
+        refc = unsafe { entry.add(vpn[i - 1]).as_ref().unwrap() };
+    }
+
+    None
+}
+
 /// Identity map a range of address
 // TODO: Write tests
 pub fn ident_map_range(root: &mut Table, start: usize, end: usize, bits: usize) {
@@ -494,6 +712,16 @@ pub fn ident_map_range(root: &mut Table, start: usize, end: usize, bits: usize)
     }
 }
 
+/// Map `size` bytes of physical memory at `paddr` to `vaddr`, one page at a
+/// time
+pub fn map_range(root: &mut Table, vaddr: usize, paddr: usize, size: usize, bits: usize) {
+    let num_pages = align_val(size, PAGE_ORDER) / PAGE_SIZE;
+
+    for i in 0..num_pages {
+        map(root, vaddr + i * PAGE_SIZE, paddr + i * PAGE_SIZE, bits, 0);
+    }
+}
+
 pub fn flush_hw_cache(asid: usize) {
     unsafe {
         asm!("sfence.vma zero, {}", in(reg)(asid));
@@ -3,10 +3,34 @@ use super::{encoding, trap};
 // How many pages we give to a given a process for their stack
 pub const STACK_PAGES: usize = 35;
 
-pub fn create_process(frame: &mut trap::TrapFrame, pc: usize, sp: usize, ra: usize, pid: usize) {
+pub fn create_process(
+    frame: &mut trap::TrapFrame,
+    pc: usize,
+    sp: usize,
+    ra: usize,
+    pid: usize,
+    satp: usize,
+) {
     frame.pc = pc;
     frame.regs[1] = ra;
-    frame.regs[2] = sp * 4096; // SP
-    frame.mode = encoding::CpuMode::Machine as usize;
+    frame.regs[2] = sp; // SP
+    frame.mode = encoding::CpuMode::User as usize;
     frame.pid = pid;
+    frame.satp = satp;
+}
+
+/// Sets up `frame` to run `pc` in supervisor mode, translating addresses
+/// with `satp`
+pub fn create_kernel_task(
+    frame: &mut trap::TrapFrame,
+    pc: usize,
+    sp: usize,
+    pid: usize,
+    satp: usize,
+) {
+    frame.pc = pc;
+    frame.regs[2] = sp; // SP
+    frame.mode = encoding::CpuMode::Supervisor as usize;
+    frame.pid = pid;
+    frame.satp = satp;
 }
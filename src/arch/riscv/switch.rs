@@ -1,16 +1,30 @@
+use super::encoding::CpuMode;
+use super::trap::TrapFrame;
 use crate::consts::SwitchMode;
 
 extern "C" {
     fn _switch_to_user(frame: usize) -> !;
+    fn _switch_to_supervisor(frame: usize) -> !;
 }
 
 pub fn switch(frame: usize, mode: SwitchMode) -> ! {
+    #[cfg(feature = "hardened")]
+    crate::harden::seal_processes();
+    crate::stats::resume();
+
+    match mode {
+        SwitchMode::User => unsafe { _switch_to_user(frame) },
+        SwitchMode::Supervisor => unsafe { _switch_to_supervisor(frame) },
+    }
+}
+
+/// Mode the task whose frame is at `frame` runs in
+pub fn mode_of(frame: usize) -> SwitchMode {
+    let mode = unsafe { (*(frame as *const TrapFrame)).mode };
+
     match mode {
-        SwitchMode::User => {
-            unsafe { _switch_to_user(frame) };
-        }
-        _ => {
-            panic!("{} not supported", mode);
-        }
+        m if m == CpuMode::User as usize => SwitchMode::User,
+        m if m == CpuMode::Supervisor as usize => SwitchMode::Supervisor,
+        _ => panic!("Frame 0x{:x} runs in unsupported mode {}", frame, mode),
     }
 }
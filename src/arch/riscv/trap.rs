@@ -5,7 +5,6 @@ Author: Ben Mezger (github.com/benmezger)
 use core::fmt;
 
 use super::encoding::{Exception, Interrupt, Register};
-use super::switch::switch;
 
 /// `TrapFrame` from a trap
 ///
@@ -59,12 +58,33 @@ impl TrapFrame {
     pub fn syscall_id(&mut self) -> usize {
         self.regs[Register::A7 as usize]
     }
+
+    /// The `n`th syscall argument, held in a0 - a5
+    pub fn arg(&self, n: usize) -> usize {
+        self.regs[Register::A0 as usize + n]
+    }
+
+    /// Sets the `n`th argument register, a0 - a7
+    pub fn set_arg(&mut self, n: usize, value: usize) {
+        self.regs[Register::A0 as usize + n] = value;
+    }
+
+    /// Sets the value a syscall returns, held in a0
+    pub fn set_return(&mut self, value: usize) {
+        self.regs[Register::A0 as usize] = value;
+    }
 }
 
 /// Handles the trap and calls `TrapFrame.handle` architecture specifc implemetation
 ///
 /// `trap_handler` returns the return address via a0
+///
+/// Only `_trap_vector` calls it, with `frame` pointing at the frame of the
+/// process the trap interrupted, which stays valid for the whole trap.
+/// Marking it unsafe would not help any caller, so the frame is dereferenced
+/// right here.
 #[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn trap_handler(
     epc: usize,
     tval: usize,
@@ -85,18 +105,27 @@ pub extern "C" fn trap_handler(
     let retpc = epc;
     let cause_num = cause & 0xfff;
 
+    if is_async && crate::kthread::in_critical() {
+        // a kernel thread is updating kernel state, let it finish before
+        // anything else touches it
+        crate::sched::arm_timer(frame as usize);
+        return;
+    }
+
+    #[cfg(feature = "hardened")]
+    crate::harden::verify_processes();
+
+    crate::stats::trap_entry(unsafe { (*frame).pid });
+
     if is_async {
         match Interrupt::from_usize(cause_num) {
             Interrupt::MachineSoftware => {
                 crate::println!("Machine software interrupt CPU#{}", hartid);
             }
             Interrupt::MachineTimer => {
-                crate::sched::init_sched(1);
-                let next_frame = crate::sched::schedule();
-
-                if next_frame != 0 {
-                    switch(next_frame, crate::consts::SwitchMode::User);
-                }
+                crate::fault::tick();
+                crate::sched::tick();
+                crate::sched::run(crate::sched::schedule());
             }
             _ => {
                 panic!("Unhandled async trap CPU#{} -> {}", hartid, cause_num);
@@ -104,21 +133,35 @@ pub extern "C" fn trap_handler(
         }
     } else {
         match Exception::from_usize(cause_num) {
-            Exception::EnvCallFromMMode => {
-                crate::println!("Environment call from M-mode");
-
+            Exception::EnvCallFromUMode
+            | Exception::EnvCallFromSMode
+            | Exception::EnvCallFromMMode => {
                 unsafe {
-                    crate::syscall::make_syscall(retpc, frame, 0);
+                    crate::syscall::make_syscall(retpc, frame);
                 }
 
-                crate::sched::init_sched(1);
-                let next_frame = crate::sched::schedule();
-                switch(next_frame, crate::consts::SwitchMode::User);
+                crate::sched::run(crate::sched::schedule());
             }
 
-            Exception::EnvCallFromSMode => {
-                crate::println!("Environment call from S-mode");
+            Exception::InstructionPageFault
+            | Exception::LoadPageFault
+            | Exception::StoreAMOPageFault => {
+                // A process touching memory outside its address space is
+                // stopped, the rest of the system keeps running
+                let pid = unsafe { (*frame).pid };
+                crate::println!(
+                    "Page fault CPU#{} pid {} -> 0x{:08x}: 0x{:08x}",
+                    hartid,
+                    pid,
+                    epc,
+                    tval
+                );
+                crate::process::with_process(pid, |p| p.usage.page_faults += 1);
+                crate::process::exit(pid, crate::process::KILLED);
+
+                crate::sched::run(crate::sched::schedule());
             }
+
             Exception::IllegaInstruction => {
                 panic!(
                     "Illegal instruction CPU#{} -> 0x{:08x}: 0x{:08x}\n",
@@ -0,0 +1,112 @@
+//! Kernel command line, the `/chosen/bootargs` property of the flattened
+//! device tree the boot loader hands over in a1
+//!
+//! Qemu sets it with `-append`. Arguments are separated by spaces and are
+//! either `key=value` or a bare `key`.
+
+use core::slice;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+/// Longest command line kept, longer ones are cut
+pub const MAX_LEN: usize = 256;
+
+static mut BOOTARGS: [u8; MAX_LEN] = [0; MAX_LEN];
+static mut LEN: usize = 0;
+
+/// Reads the big endian word at `offset` bytes from `base`
+unsafe fn word(base: *const u8, offset: usize) -> u32 {
+    u32::from_be((base.add(offset) as *const u32).read())
+}
+
+/// The NUL terminated string at `ptr`, without its NUL, at most `max` bytes
+unsafe fn c_str(ptr: *const u8, max: usize) -> &'static [u8] {
+    let len = (0..max).find(|&i| *ptr.add(i) == 0).unwrap_or(max);
+    slice::from_raw_parts(ptr, len)
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// Walks the structure block of the device tree at `dtb` and returns the
+/// value of `/chosen/bootargs`
+unsafe fn find(dtb: *const u8) -> Option<&'static [u8]> {
+    if dtb.is_null() || dtb as usize % 4 != 0 || word(dtb, 0) != FDT_MAGIC {
+        return None;
+    }
+    let structs = dtb.add(word(dtb, 8) as usize);
+    let strings = dtb.add(word(dtb, 12) as usize);
+    let size = word(dtb, 36) as usize;
+
+    let mut offset = 0;
+    let mut depth = 0usize;
+    let mut in_chosen = false;
+    while offset < size {
+        let token = word(structs, offset);
+        offset += 4;
+
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = c_str(structs.add(offset), size - offset);
+                depth += 1;
+                // the root node is at depth 1 and has an empty name
+                in_chosen = depth == 2 && name == b"chosen";
+                offset = align4(offset + name.len() + 1);
+            }
+            FDT_END_NODE => {
+                depth = depth.saturating_sub(1);
+                in_chosen = false;
+            }
+            FDT_PROP => {
+                let len = word(structs, offset) as usize;
+                let name = c_str(strings.add(word(structs, offset + 4) as usize), 32);
+                if in_chosen && name == b"bootargs" {
+                    return Some(c_str(structs.add(offset + 8), len));
+                }
+                offset = align4(offset + 8 + len);
+            }
+            FDT_NOP => {}
+            // FDT_END, or not a device tree after all
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Copies the command line out of the device tree at `dtb`
+///
+/// Must run before the page allocator is set up, which may hand out the
+/// memory holding the device tree. Without a device tree, as on boards whose
+/// boot loader does not pass one, the command line stays empty.
+///
+/// # Safety
+///
+/// `dtb` must be null, or readable and 4 byte aligned.
+pub unsafe fn init(dtb: usize) {
+    let args = find(dtb as *const u8).unwrap_or(&[]);
+    let len = args.len().min(MAX_LEN);
+
+    BOOTARGS[..len].copy_from_slice(&args[..len]);
+    LEN = len;
+}
+
+/// The command line, empty if there is none or it is not UTF-8
+pub fn get() -> &'static str {
+    unsafe { core::str::from_utf8(&BOOTARGS[..LEN]).unwrap_or("") }
+}
+
+/// Value of the `key=value` argument, `Some("")` for a bare `key`
+pub fn value(key: &str) -> Option<&'static str> {
+    get()
+        .split(' ')
+        .find_map(|arg| match arg.strip_prefix(key) {
+            Some("") => Some(""),
+            Some(rest) => rest.strip_prefix('='),
+            None => None,
+        })
+}
@@ -8,6 +8,13 @@ use core::fmt;
 pub const FREQ: u64 = 10_000_000;
 // Let's do this 250 times per second for switching
 pub const CONTEXT_SWITCH_TIME: u64 = FREQ / 250;
+// Processes run for this many CONTEXT_SWITCH_TIME unless they ask otherwise
+pub const DEFAULT_QUANTUM: u16 = 1;
+// Replicas have one second to submit their results before the watchdog votes
+// without them
+pub const REPLICA_TIMEOUT: u64 = FREQ;
+// Kernel threads sleep this many milliseconds between two rounds of work
+pub const KTHREAD_PERIOD_MS: usize = 10;
 
 extern "C" {
     pub static HEAP_START: usize;
@@ -46,6 +53,13 @@ cfg_if::cfg_if! {
 
 pub const PROCESS_START_ADDR: usize = 0x2000_0000;
 
+// Memory mapped devices of the QEMU virt machine the kernel maps for itself
+pub const CLINT_ADDRESS: usize = 0x0200_0000;
+pub const CLINT_SIZE: usize = 0x1_0000;
+pub const UART_SIZE: usize = 0x100;
+pub const TEST_DEVICE_ADDRESS: usize = 0x10_0000;
+pub const TEST_DEVICE_SIZE: usize = 0x1000;
+
 pub enum SwitchMode {
     User,
     Supervisor,
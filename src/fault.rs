@@ -0,0 +1,481 @@
+use crate::arch::isa::page::PAGE_SIZE;
+use crate::arch::isa::trap::TrapFrame;
+use crate::replica::Vote;
+use crate::{bootargs, consts, process, replica};
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem::size_of;
+use riscv::register::cycle;
+
+const USIZE_BITS: usize = size_of::<usize>() * 8;
+
+/// Where a fault is injected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// A general purpose register saved in the replica's `TrapFrame`
+    Register(usize),
+    /// A byte offset into the replica's stack pages
+    Stack(usize),
+    /// The next result the replica submits to its group
+    Result,
+}
+
+/// A fault flipping `bit` of `target` in replica `replica` of group `gid`
+/// once `mcycle` reaches `cycle`
+///
+/// Faults are checked on every machine timer interrupt, so a fault lands on
+/// the first tick after `cycle`.
+#[derive(Debug, Clone, Copy)]
+pub struct Fault {
+    pub gid: usize,
+    pub replica: usize,
+    pub target: Target,
+    pub bit: usize,
+    pub cycle: u64,
+}
+
+/// Faults drawn from a seeded PRNG
+#[derive(Debug, Clone, Copy)]
+pub struct RandomFaults {
+    pub seed: u64,
+    pub gid: usize,
+    /// How many faults to inject in total
+    pub count: usize,
+    /// On average, one in `rate` timer interrupts injects a fault
+    pub rate: u64,
+}
+
+/// A fault injection campaign, set up at boot by `init`
+#[derive(Debug, Clone)]
+pub struct Campaign {
+    pub faults: Vec<Fault>,
+    pub random: Option<RandomFaults>,
+}
+
+/// Why a campaign could not be parsed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CampaignError {
+    /// An item is neither `fault=` nor `random=`
+    UnknownItem,
+    /// An item has too few or too many `:` separated fields
+    FieldCount,
+    BadNumber,
+    /// A fault target is not `x<register>`, `stack+<offset>` or `result`
+    BadTarget,
+}
+
+impl fmt::Display for CampaignError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CampaignError::UnknownItem => write!(f, "expected fault= or random="),
+            CampaignError::FieldCount => write!(f, "wrong number of fields"),
+            CampaignError::BadNumber => write!(f, "bad number"),
+            CampaignError::BadTarget => write!(f, "bad fault target"),
+        }
+    }
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal number
+fn number(field: &str) -> Result<u64, CampaignError> {
+    let parsed = match field.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => field.parse(),
+    };
+    parsed.map_err(|_| CampaignError::BadNumber)
+}
+
+fn target(field: &str) -> Result<Target, CampaignError> {
+    if field == "result" {
+        Ok(Target::Result)
+    } else if let Some(reg) = field.strip_prefix('x') {
+        Ok(Target::Register(number(reg)? as usize))
+    } else if let Some(offset) = field.strip_prefix("stack+") {
+        Ok(Target::Stack(number(offset)? as usize))
+    } else {
+        Err(CampaignError::BadTarget)
+    }
+}
+
+impl Campaign {
+    pub const fn none() -> Self {
+        Campaign {
+            faults: Vec::new(),
+            random: None,
+        }
+    }
+
+    /// Parses a campaign made of items separated by spaces or `;`
+    ///
+    /// `fault=<gid>:<replica>:<target>:<bit>:<cycle>` adds a fault, where
+    /// `<target>` is `x<register>`, `stack+<offset>` or `result`.
+    /// `random=<seed>:<gid>:<count>:<rate>` draws faults at random, see
+    /// `RandomFaults`. Numbers are decimal or `0x` prefixed hexadecimal.
+    pub fn parse(spec: &str) -> Result<Self, CampaignError> {
+        let mut campaign = Campaign::none();
+
+        for item in spec
+            .split(|c: char| c == ';' || c.is_whitespace())
+            .filter(|item| !item.is_empty())
+        {
+            let fields: Vec<&str> = item[item.find('=').map_or(0, |at| at + 1)..]
+                .split(':')
+                .collect();
+
+            if item.starts_with("fault=") {
+                if fields.len() != 5 {
+                    return Err(CampaignError::FieldCount);
+                }
+                campaign.faults.push(Fault {
+                    gid: number(fields[0])? as usize,
+                    replica: number(fields[1])? as usize,
+                    target: target(fields[2])?,
+                    bit: number(fields[3])? as usize,
+                    cycle: number(fields[4])?,
+                });
+            } else if item.starts_with("random=") {
+                if fields.len() != 4 {
+                    return Err(CampaignError::FieldCount);
+                }
+                campaign.random = Some(RandomFaults {
+                    seed: number(fields[0])?,
+                    gid: number(fields[1])? as usize,
+                    count: number(fields[2])? as usize,
+                    rate: number(fields[3])?,
+                });
+            } else {
+                return Err(CampaignError::UnknownItem);
+            }
+        }
+        Ok(campaign)
+    }
+}
+
+/// The campaign the kernel runs at boot, taken from the `strail.campaign`
+/// boot argument, see `bootargs` and `Campaign::parse`
+///
+/// Boot arguments are separated by spaces, so the items of the campaign are
+/// separated by `;`.
+///
+/// # Panics
+///
+/// Panics if `strail.campaign` is not a valid campaign.
+pub fn boot_campaign() -> Campaign {
+    match bootargs::value("strail.campaign") {
+        Some(spec) => match Campaign::parse(spec) {
+            Ok(campaign) => campaign,
+            Err(err) => panic!("Invalid strail.campaign {:?}: {}", spec, err),
+        },
+        None => Campaign::none(),
+    }
+}
+
+/// A fault that was injected
+#[derive(Debug, Clone, Copy)]
+pub struct Injection {
+    pub cycle: u64,
+    pub gid: usize,
+    pub replica: usize,
+    /// Run of a temporal group the fault landed in, `None` in spatial groups
+    pub run: Option<usize>,
+    pub target: Target,
+    pub bit: usize,
+    pub before: usize,
+    pub after: usize,
+    /// What the first vote after the injection made of it
+    pub verdict: Option<Verdict>,
+}
+
+/// Whether a vote caught an injected fault
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    /// The voter flagged the faulty replica
+    Detected,
+    /// The faulty replica still agreed with the majority
+    Masked,
+    /// No majority was found
+    Unmasked,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Detected => write!(f, "detected"),
+            Verdict::Masked => write!(f, "masked"),
+            Verdict::Unmasked => write!(f, "unmasked"),
+        }
+    }
+}
+
+impl Injection {
+    /// Index the fault's result has in a vote: the run in temporal groups,
+    /// the replica otherwise
+    fn slot(&self) -> usize {
+        self.run.unwrap_or(self.replica)
+    }
+}
+
+impl fmt::Display for Injection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "FAULT cycle={} gid={} replica={}",
+            self.cycle, self.gid, self.replica
+        )?;
+        if let Some(run) = self.run {
+            write!(f, " run={}", run)?;
+        }
+        write!(
+            f,
+            " target={:?} bit={} before=0x{:x} after=0x{:x}",
+            self.target, self.bit, self.before, self.after
+        )
+    }
+}
+
+/// xorshift64* generator, enough to make campaigns reproducible
+pub struct Prng(u64);
+
+impl Prng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck at zero
+        Prng(if seed == 0 {
+            0x9e37_79b9_7f4a_7c15
+        } else {
+            seed
+        })
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number in `0..n`
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+struct Injector {
+    pending: Vec<Fault>,
+    random: Option<RandomFaults>,
+    prng: Prng,
+    /// Result faults waiting for their replica to submit
+    armed: Vec<Fault>,
+    log: Vec<Injection>,
+}
+
+static mut INJECTOR: Option<Injector> = None;
+
+pub fn init(campaign: Campaign) {
+    let seed = campaign.random.map(|r| r.seed).unwrap_or(0);
+
+    unsafe {
+        INJECTOR = Some(Injector {
+            pending: campaign.faults,
+            random: campaign.random,
+            prng: Prng::new(seed),
+            armed: Vec::new(),
+            log: Vec::new(),
+        });
+    }
+}
+
+fn flip(value: usize, bit: usize) -> usize {
+    value ^ (1 << (bit % USIZE_BITS))
+}
+
+fn record(injector: &mut Injector, fault: &Fault, before: usize, after: usize) {
+    let injection = Injection {
+        cycle: cycle::read64(),
+        gid: fault.gid,
+        replica: fault.replica,
+        run: replica::with_group(fault.gid, |g| g.current_run()).flatten(),
+        target: fault.target,
+        bit: fault.bit,
+        before,
+        after,
+        verdict: None,
+    };
+
+    crate::println!("{}", injection);
+    injector.log.push(injection);
+}
+
+/// Flips a bit in the replica's frame or stack, or arms a result fault
+fn inject(injector: &mut Injector, fault: Fault) {
+    if fault.target == Target::Result {
+        injector.armed.push(fault);
+        return;
+    }
+
+    let pid = match replica::with_group(fault.gid, |g| g.pid_of(fault.replica)).flatten() {
+        Some(pid) => pid,
+        None => {
+            crate::println!("Fault on missing replica {}:{}", fault.gid, fault.replica);
+            return;
+        }
+    };
+
+    let addrs = process::with_process(pid, |p| {
+        (p.get_frame_addr(), p.get_stack_addr(), p.stack_size())
+    });
+    let (frame_addr, stack_addr, stack_size) = match addrs {
+        Some(addrs) => addrs,
+        None => return,
+    };
+
+    match fault.target {
+        Target::Register(reg) => {
+            let frame = unsafe { &mut *(frame_addr as *mut TrapFrame) };
+            let reg = reg % frame.regs.len();
+            let before = frame.regs[reg];
+
+            frame.regs[reg] = flip(before, fault.bit);
+            record(injector, &fault, before, frame.regs[reg]);
+        }
+        Target::Stack(offset) => {
+            let offset = offset % stack_size;
+            let byte = unsafe { &mut *(stack_addr as *mut u8).add(offset) };
+            let before = *byte;
+
+            *byte ^= 1 << (fault.bit % 8);
+            record(injector, &fault, before as usize, *byte as usize);
+        }
+        Target::Result => {}
+    }
+}
+
+/// Draws a random fault for `random`'s group
+fn draw(prng: &mut Prng, random: &RandomFaults) -> Option<Fault> {
+    let replicas = replica::with_group(random.gid, |g| g.members().len())?;
+    if replicas == 0 {
+        return None;
+    }
+
+    let replica = prng.below(replicas);
+    let target = match prng.below(3) {
+        // leave x0 alone, flipping it does nothing
+        0 => Target::Register(1 + prng.below(31)),
+        1 => Target::Stack(prng.below(consts::STACK_PAGES * PAGE_SIZE)),
+        _ => Target::Result,
+    };
+
+    Some(Fault {
+        gid: random.gid,
+        replica,
+        target,
+        bit: prng.below(USIZE_BITS),
+        cycle: cycle::read64(),
+    })
+}
+
+/// Injects every fault that is due
+///
+/// Called on every machine timer interrupt.
+pub fn tick() {
+    let injector = match unsafe { INJECTOR.as_mut() } {
+        Some(injector) => injector,
+        None => return,
+    };
+
+    let now = cycle::read64();
+    let mut i = 0;
+    while i < injector.pending.len() {
+        if injector.pending[i].cycle <= now {
+            let fault = injector.pending.remove(i);
+            inject(injector, fault);
+        } else {
+            i += 1;
+        }
+    }
+
+    if let Some(mut random) = injector.random {
+        if random.count > 0 && injector.prng.next_u64() % random.rate.max(1) == 0 {
+            if let Some(fault) = draw(&mut injector.prng, &random) {
+                random.count -= 1;
+                injector.random = Some(random);
+                inject(injector, fault);
+            }
+        }
+    }
+}
+
+/// Applies any armed result fault to the `value` submitted by a replica
+pub fn corrupt_result(gid: usize, replica: usize, value: usize) -> usize {
+    let injector = match unsafe { INJECTOR.as_mut() } {
+        Some(injector) => injector,
+        None => return value,
+    };
+
+    let armed = injector
+        .armed
+        .iter()
+        .position(|f| f.gid == gid && f.replica == replica);
+
+    match armed {
+        Some(i) => {
+            let fault = injector.armed.remove(i);
+            let corrupted = flip(value, fault.bit);
+            record(injector, &fault, value, corrupted);
+            corrupted
+        }
+        None => value,
+    }
+}
+
+/// Compares the outcome of a vote of `gid` with the faults injected into the
+/// group since its last vote and records a `Verdict` for each
+///
+/// The dissenters of a temporal group are runs, not replicas.
+pub fn report_vote(gid: usize, outcome: &Vote) {
+    let injector = match unsafe { INJECTOR.as_mut() } {
+        Some(injector) => injector,
+        None => return,
+    };
+
+    for injection in injector
+        .log
+        .iter_mut()
+        .filter(|i| i.gid == gid && i.verdict.is_none())
+    {
+        let verdict = match outcome {
+            Vote::NoMajority => Verdict::Unmasked,
+            Vote::Majority { dissenters, .. } if dissenters.contains(&injection.slot()) => {
+                Verdict::Detected
+            }
+            _ => Verdict::Masked,
+        };
+
+        injection.verdict = Some(verdict);
+        let (label, slot) = match injection.run {
+            Some(run) => ("run", run),
+            None => ("replica", injection.replica),
+        };
+        crate::println!(
+            "FAULT-VOTE gid={} {}={} cycle={} outcome={}",
+            gid,
+            label,
+            slot,
+            injection.cycle,
+            verdict
+        );
+    }
+}
+
+/// Every fault injected so far, oldest first
+pub fn log() -> Vec<Injection> {
+    unsafe { INJECTOR.as_ref().map(|i| i.log.clone()).unwrap_or_default() }
+}
+
+pub fn print_log() {
+    unsafe {
+        if let Some(injector) = INJECTOR.as_ref() {
+            for injection in injector.log.iter() {
+                crate::println!("{}", injection);
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use crate::arch;
+use crate::arch::isa::page::{align_val, PAGE_SIZE};
+use crate::arch::isa::trap::TrapFrame;
+use crate::cpu::KERNEL_TRAP_FRAME;
+use crate::process::PROCESS_TABLE;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr::{self, null_mut};
+use core::slice;
+
+/// FNV-1a hasher used to checksum kernel structures
+pub struct Checksum(u64);
+
+impl Checksum {
+    pub fn new() -> Self {
+        Checksum(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100_0000_01b3);
+        }
+    }
+
+    pub fn write_usize(&mut self, value: usize) {
+        self.write(&value.to_ne_bytes());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut sum = Checksum::new();
+    sum.write(bytes);
+    sum.finish()
+}
+
+/// A memory region guarded by a checksum and a redundant copy
+struct Guarded {
+    name: &'static str,
+    live: *mut u8,
+    shadow: *mut u8,
+    len: usize,
+    sum: u64,
+}
+
+impl Guarded {
+    fn live(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.live, self.len) }
+    }
+
+    fn shadow(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.shadow, self.len) }
+    }
+
+    fn seal(&mut self) {
+        unsafe { ptr::copy_nonoverlapping(self.live, self.shadow, self.len) };
+        self.sum = checksum(self.live());
+    }
+
+    /// Repairs whichever of the live and redundant copies no longer matches
+    /// the checksum, and panics if neither does
+    fn scrub(&mut self) {
+        let live_ok = checksum(self.live()) == self.sum;
+        let shadow_ok = checksum(self.shadow()) == self.sum;
+
+        match (live_ok, shadow_ok) {
+            (true, true) => {}
+            (true, false) => {
+                crate::println!("hardened: {} redundant copy repaired", self.name);
+                unsafe { ptr::copy_nonoverlapping(self.live, self.shadow, self.len) };
+            }
+            (false, true) => {
+                for (offset, (live, shadow)) in
+                    self.live().iter().zip(self.shadow().iter()).enumerate()
+                {
+                    if live != shadow {
+                        crate::println!(
+                            "hardened: {} byte {} at {:p} repaired 0x{:02x} -> 0x{:02x}",
+                            self.name,
+                            offset,
+                            unsafe { self.live.add(offset) },
+                            live,
+                            shadow
+                        );
+                    }
+                }
+                unsafe { ptr::copy_nonoverlapping(self.shadow, self.live, self.len) };
+            }
+            (false, false) => panic!(
+                "hardened: {} at {:p} ({} bytes) and its copy are both corrupted",
+                self.name, self.live, self.len
+            ),
+        }
+    }
+}
+
+struct Hardened {
+    trap_frames: Guarded,
+    pages: Guarded,
+    /// Checksum of every process, keyed by pid
+    processes: Vec<(usize, u64)>,
+}
+
+static mut HARDENED: Option<Hardened> = None;
+
+fn pages_of(len: usize) -> usize {
+    align_val(len, 12) / PAGE_SIZE
+}
+
+/// Allocates the redundant copies and seals every guarded structure
+///
+/// Must run after `arch::kmem::init`.
+pub fn init() {
+    let trap_frames_len = size_of::<[TrapFrame; 8]>();
+    let (descriptors, descriptors_len) = arch::mem::descriptors();
+
+    let mut hardened = Hardened {
+        trap_frames: Guarded {
+            name: "KERNEL_TRAP_FRAME",
+            live: unsafe { KERNEL_TRAP_FRAME.as_mut_ptr() as *mut u8 },
+            shadow: arch::mem::zalloc(pages_of(trap_frames_len)),
+            len: trap_frames_len,
+            sum: 0,
+        },
+        pages: Guarded {
+            name: "page descriptors",
+            live: descriptors,
+            shadow: null_mut(),
+            len: descriptors_len,
+            sum: 0,
+        },
+        processes: Vec::new(),
+    };
+    // allocating the shadow changes the descriptors, seal them afterwards
+    hardened.pages.shadow = arch::mem::zalloc(pages_of(descriptors_len));
+
+    hardened.trap_frames.seal();
+    hardened.pages.seal();
+
+    unsafe {
+        HARDENED = Some(hardened);
+    }
+    seal_processes();
+}
+
+/// Seals the page descriptors after they legitimately changed
+pub fn seal_pages() {
+    if let Some(hardened) = unsafe { HARDENED.as_mut() } {
+        hardened.pages.seal();
+    }
+}
+
+/// Seals the process table, called before leaving the kernel
+pub fn seal_processes() {
+    let hardened = match unsafe { HARDENED.as_mut() } {
+        Some(hardened) => hardened,
+        None => return,
+    };
+
+    hardened.processes.clear();
+    if let Some(pt) = unsafe { PROCESS_TABLE.as_ref() } {
+        for proc in pt.iter() {
+            hardened.processes.push((proc.pid, proc.checksum()));
+        }
+    }
+}
+
+/// Checks the process table against its seal, called when entering the kernel
+///
+/// Processes carry no redundant copy, so any mismatch panics with the pid
+/// involved.
+pub fn verify_processes() {
+    let hardened = match unsafe { HARDENED.as_ref() } {
+        Some(hardened) => hardened,
+        None => return,
+    };
+    let pt = match unsafe { PROCESS_TABLE.as_ref() } {
+        Some(pt) => pt,
+        None => return,
+    };
+
+    if pt.len() != hardened.processes.len() {
+        panic!(
+            "hardened: PROCESS_TABLE holds {} processes, {} were sealed",
+            pt.len(),
+            hardened.processes.len()
+        );
+    }
+
+    for proc in pt.iter() {
+        match hardened.processes.iter().find(|(pid, _)| *pid == proc.pid) {
+            Some((_, sum)) if *sum == proc.checksum() => {}
+            Some((_, sum)) => panic!(
+                "hardened: process {} corrupted, checksum 0x{:016x} expected 0x{:016x}",
+                proc.pid,
+                proc.checksum(),
+                sum
+            ),
+            None => panic!("hardened: process {} was never sealed", proc.pid),
+        }
+    }
+}
+
+/// Verifies every guarded structure, repairing what has a healthy copy
+///
+/// Called on every machine timer interrupt.
+pub fn scrub() {
+    if let Some(hardened) = unsafe { HARDENED.as_mut() } {
+        hardened.trap_frames.scrub();
+        hardened.pages.scrub();
+    }
+    verify_processes();
+}
@@ -3,6 +3,7 @@ Author: Ben Mezger (github.com/benmezger)
 */
 
 use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
 
 struct GHeapAllocator;
 
@@ -10,16 +11,19 @@ struct GHeapAllocator;
 static mut CALL_COUNT: usize = 0;
 
 unsafe impl GlobalAlloc for GHeapAllocator {
-    unsafe fn alloc(&self, _layout: Layout) -> *mut u8 {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         cfg_if::cfg_if! {
             if #[cfg(test)] {
                 CALL_COUNT += 1;
             }
         }
 
-        crate::arch::kmem::kzmalloc(_layout.size())
+        // the error handler reports the failed layout
+        crate::arch::kmem::kzmalloc_aligned(layout.size(), layout.align()).unwrap_or(null_mut())
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        crate::arch::kmem::kfree(ptr)
     }
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
 }
 
 #[global_allocator]
@@ -0,0 +1,91 @@
+use crate::{consts, process, replica, sched, syscall};
+
+/// Pages of stack each kernel thread gets
+const STACK_PAGES: usize = 4;
+
+/// Set while a kernel thread is inside `critical`
+static mut CRITICAL: bool = false;
+
+/// Entry point of kernel threads, `start_kernel_thread` puts the thread's
+/// body in a0
+fn trampoline(entry: usize) {
+    let entry: fn() = unsafe { core::mem::transmute(entry) };
+
+    entry();
+    syscall::syscall_exit(0);
+}
+
+/// Starts a kernel thread running `entry` and returns its pid
+///
+/// The thread exits with status `0` once `entry` returns.
+pub fn spawn(entry: fn()) -> usize {
+    process::start_kernel_thread(trampoline as usize, entry as usize, STACK_PAGES)
+}
+
+/// Runs `f` without being preempted
+///
+/// Machine timer interrupts cannot be masked from supervisor mode, so the
+/// trap handler checks `in_critical` and returns straight to the thread
+/// instead of scheduling. Kernel threads touching state the trap handler
+/// also uses do so through this.
+pub fn critical<R>(f: impl FnOnce() -> R) -> R {
+    unsafe { CRITICAL = true };
+    let ret = f();
+
+    // the thread may have changed processes legitimately
+    #[cfg(feature = "hardened")]
+    crate::harden::seal_processes();
+    unsafe { CRITICAL = false };
+    ret
+}
+
+/// Whether a kernel thread is inside `critical`
+pub fn in_critical() -> bool {
+    unsafe { CRITICAL }
+}
+
+/// Expires the watchdog of replica groups that missed their deadline
+fn watchdog() {
+    loop {
+        critical(|| replica::check_deadlines(sched::get_mtime()));
+        syscall::syscall_sleep(consts::KTHREAD_PERIOD_MS);
+    }
+}
+
+/// Votes for the replica groups whose replicas all submitted
+fn voter() {
+    loop {
+        critical(|| {
+            for (gid, outcome) in replica::vote_pending() {
+                if let Some(value) = outcome.value() {
+                    crate::println!("Correct output: {}", value);
+                }
+
+                let members = replica::with_group(gid, |g| g.live_members());
+                for pid in members.unwrap_or_default() {
+                    process::with_process(pid, |p| {
+                        crate::println!("Process {}: {}", pid, p.usage);
+                    });
+                }
+            }
+        });
+        syscall::syscall_sleep(consts::KTHREAD_PERIOD_MS);
+    }
+}
+
+/// Repairs corrupted kernel structures from their redundant copies
+#[cfg(feature = "hardened")]
+fn scrubber() {
+    loop {
+        critical(crate::harden::scrub);
+        syscall::syscall_sleep(consts::KTHREAD_PERIOD_MS);
+    }
+}
+
+/// Starts the watchdog, voter and, when hardened, scrubber threads
+pub fn init() {
+    spawn(watchdog);
+    spawn(voter);
+    #[cfg(feature = "hardened")]
+    spawn(scrubber);
+}
@@ -28,11 +28,20 @@ pub mod uart;
 pub mod page;
 
 pub mod arch;
+pub mod bootargs;
 pub mod cpu;
 pub mod exit;
+pub mod fault;
+#[cfg(feature = "hardened")]
+pub mod harden;
 mod heap;
+pub mod kthread;
 pub mod process;
+pub mod replica;
 pub mod sched;
+pub mod signal;
+pub mod slab;
+pub mod stats;
 pub mod syscall;
 
 extern crate alloc;
@@ -190,6 +199,7 @@ where
 ///
 /// This is expected to never return
 pub fn exit_qemu_as_failure() -> ! {
+    stats::report();
     arch::sys::exit(exit::ExitCode::Failed);
     // we shouldn't get here
     panic!("We shoudn't get here.");
@@ -199,6 +209,7 @@ pub fn exit_qemu_as_failure() -> ! {
 ///
 /// This is expected to never return
 pub fn exit_qemu_as_success() -> ! {
+    stats::report();
     arch::sys::exit(exit::ExitCode::Success);
     // we shouldn't get here
     panic!("We shoudn't get here.");
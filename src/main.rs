@@ -59,21 +59,27 @@ fn kinfo() {
 // This is called from boot.S
 #[cfg(not(test))]
 #[no_mangle]
-extern "C" fn main() {
+extern "C" fn main(dtb: usize) {
+    unsafe { bootargs::init(dtb) };
     strail::uart::Uart::init();
     crate::arch::mem::init();
     crate::arch::kmem::init();
+    stats::init();
+    fault::init(fault::boot_campaign());
+    #[cfg(feature = "hardened")]
+    harden::init();
 
     println!("Initializing the kernel..");
     kinfo();
 
     arch::mem::print_page_allocation();
 
+    sched::init(sched::DEFAULT_POLICY);
     let ret = process::init();
     println!("Init process created at address 0x{:08x}", ret);
+    kthread::init();
 
-    sched::init_sched(2);
-    arch::frame::switch(sched::schedule(), consts::SwitchMode::User);
+    sched::run(sched::schedule());
 }
 
 // The kernel's test main function
@@ -14,10 +14,12 @@ pub trait Table {
 
 #[derive(Copy, Clone)]
 pub enum PageBits {
+    UserRead,
     UserReadExecute,
     UserReadWrite,
     UserReadWriteExecute,
 
+    Read,
     ReadExecute,
     ReadWrite,
 }
@@ -5,15 +5,23 @@ pub enum State {
     Running,
     Sleeping,
     Waiting,
+    /// Submitted a result its replica group has not voted on yet
+    Voting,
     Dead,
 }
 
-use crate::{arch, consts, syscall};
+use crate::page::PageBits;
+use crate::signal::Signal;
+use crate::{arch, consts, replica, sched, slab, stats, syscall};
 use alloc::collections::vec_deque::VecDeque;
+use alloc::vec::Vec;
 use core::{fmt, ptr::null_mut};
-use riscv::register::mcycle;
 
-static mut NEXT_PID: usize = 1;
+/// Exit status of processes killed by the kernel
+///
+/// Distinct from the `usize::MAX` syscalls return on error, so `wait` on a
+/// killed child can be told apart from `wait` on no child.
+pub const KILLED: usize = usize::MAX - 1;
 
 #[derive(Debug, Clone)]
 pub struct ProcessData {
@@ -24,29 +32,321 @@ impl ProcessData {
     pub fn zero() -> Self {
         ProcessData { cwd_path: [0; 128] }
     }
-
 }
 
 #[repr(C)]
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Process {
     pub state: State,
     pub pid: usize,
-    pub is_tmr: bool,
+    pub group: Option<usize>,
+    pub replica: usize,
+    /// Process that spawned this one, `None` for processes started by the
+    /// kernel and for orphans
+    pub parent: Option<usize>,
+    /// Scheduling priority, see `sched::Priority`
+    pub priority: usize,
+    /// Absolute `mtime` deadline, see `sched::Edf`
+    pub deadline: Option<u64>,
+    /// Status passed to `exit`, kept until the process is reaped
+    exit_status: Option<usize>,
+    /// Parent blocked in `wait` on this process
+    waited_by: Option<usize>,
+    /// Address of the handler run on `Signal::User`, `0` if there is none
+    handler: usize,
+    /// Signals waiting for the running handler to return, one bit each
+    pending: usize,
+    /// Stopped by `signal::Signal::Stop`, not scheduled until `Continue`
+    /// whatever its state
+    stopped: bool,
+    /// Context interrupted by the running handler, null outside handlers
+    signal_frame: *mut arch::isa::trap::TrapFrame,
     stack: *mut u8,
+    stack_pages: usize,
     frame: *mut arch::isa::trap::TrapFrame,
     root: *mut arch::isa::page::Table,
     data: ProcessData,
+    data_pages: *mut u8,
     program: *mut u8,
-    sleep_until: usize,
+    /// `mtime` at which a sleeping process wakes up
+    sleep_until: u64,
+    /// Runs in supervisor mode in the kernel's address space, see `kthread`
+    kernel: bool,
+    pub usage: Usage,
+}
+
+/// Every process, indexed by pid
+///
+/// Pid `0` is never handed out, it stands for the kernel and the idle task.
+/// Freed pids are reused oldest first, to keep a pid from naming two
+/// processes in quick succession. `exit` clears the pid from the scheduler,
+/// the sleep queue, the process's replica group and its children before it
+/// can be reused. The pid is also the process's ASID, which is safe to reuse
+/// since every switch to a process flushes the whole TLB.
+pub struct ProcessTable {
+    slots: Vec<Option<slab::Owned<Process>>>,
+    free: VecDeque<usize>,
+    len: usize,
+}
+
+impl ProcessTable {
+    pub fn new() -> Self {
+        let mut slots = Vec::with_capacity(16);
+        slots.push(None);
+
+        ProcessTable {
+            slots,
+            free: VecDeque::new(),
+            len: 0,
+        }
+    }
+
+    /// Hands out a pid for a process about to be inserted
+    pub fn reserve(&mut self) -> usize {
+        match self.free.pop_front() {
+            Some(pid) => pid,
+            None => {
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    /// Moves `proc` to the process cache, in the slot of its pid, which
+    /// must have been reserved
+    pub fn insert(&mut self, proc: Process) -> bool {
+        match self.slots.get_mut(proc.pid) {
+            Some(slot) if slot.is_none() && proc.pid != 0 => match slab::Owned::new(proc) {
+                Some(proc) => {
+                    *slot = Some(proc);
+                    self.len += 1;
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    pub fn get(&self, pid: usize) -> Option<&Process> {
+        self.slots.get(pid)?.as_deref()
+    }
+
+    pub fn get_mut(&mut self, pid: usize) -> Option<&mut Process> {
+        self.slots.get_mut(pid)?.as_deref_mut()
+    }
+
+    /// Frees `pid`, reserved for a process that was never inserted
+    pub fn release(&mut self, pid: usize) {
+        let reserved = match self.slots.get(pid) {
+            Some(slot) => slot.is_none() && pid != 0,
+            None => false,
+        };
+
+        if reserved && !self.free.contains(&pid) {
+            self.free.push_back(pid);
+        }
+    }
+
+    /// Takes `pid` out of the table and frees its pid
+    pub fn remove(&mut self, pid: usize) -> Option<Process> {
+        let proc = self.slots.get_mut(pid)?.take()?;
+
+        self.len -= 1;
+        self.free.push_back(pid);
+        Some(proc.into_inner())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Process> {
+        self.slots.iter().filter_map(|slot| slot.as_deref())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Process> {
+        self.slots.iter_mut().filter_map(|slot| slot.as_deref_mut())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Default for ProcessTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub static mut PROCESS_TABLE: Option<ProcessTable> = None;
+
+/// `syscall_getrusage` fields
+pub const USAGE_CYCLES: usize = 0;
+pub const USAGE_INSTRUCTIONS: usize = 1;
+pub const USAGE_SWITCHES: usize = 2;
+pub const USAGE_PAGE_FAULTS: usize = 3;
+/// Syscalls of every type
+pub const USAGE_SYSCALLS: usize = 4;
+/// Syscalls of type `n` are at `USAGE_SYSCALL_BASE + n`
+pub const USAGE_SYSCALL_BASE: usize = 0x100;
+
+/// Resources used by a process
+#[derive(Debug, Clone, Copy)]
+pub struct Usage {
+    /// `mcycle` cycles spent running the process
+    pub cycles: u64,
+    /// `minstret` instructions retired while running the process
+    pub instructions: u64,
+    /// Times the scheduler picked the process
+    pub switches: u64,
+    pub page_faults: u64,
+    /// Syscalls made, indexed by `syscall::Syscall`
+    pub syscalls: [u64; syscall::SYSCALL_COUNT],
 }
 
-pub static mut PROCESS_LIST: Option<VecDeque<Process>> = None;
-pub static mut TMR_VALUES_LIST: Option<VecDeque<usize>> = None;
-pub static mut time_total:f32 = 0.0;
-pub static mut total:usize = 0;
-pub static mut count:u32 = 0;
-pub static mut TMR_BOOL:bool = false;
+impl Usage {
+    pub const fn zero() -> Self {
+        Usage {
+            cycles: 0,
+            instructions: 0,
+            switches: 0,
+            page_faults: 0,
+            syscalls: [0; syscall::SYSCALL_COUNT],
+        }
+    }
+
+    pub fn count_syscall(&mut self, id: usize) {
+        if let Some(count) = self.syscalls.get_mut(id) {
+            *count += 1;
+        }
+    }
+
+    /// Value of the `USAGE_*` field `field`
+    pub fn field(&self, field: usize) -> Option<u64> {
+        match field {
+            USAGE_CYCLES => Some(self.cycles),
+            USAGE_INSTRUCTIONS => Some(self.instructions),
+            USAGE_SWITCHES => Some(self.switches),
+            USAGE_PAGE_FAULTS => Some(self.page_faults),
+            USAGE_SYSCALLS => Some(self.syscalls.iter().sum()),
+            _ => self
+                .syscalls
+                .get(field.checked_sub(USAGE_SYSCALL_BASE)?)
+                .copied(),
+        }
+    }
+}
+
+/// `key=value` pairs, syscalls are listed by number
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cycles={} instructions={} switches={} page_faults={} syscalls=",
+            self.cycles, self.instructions, self.switches, self.page_faults
+        )?;
+        for (id, count) in self.syscalls.iter().enumerate() {
+            if id > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Usage of the process `pid`, and its group and replica id if it is a
+/// replica
+#[derive(Debug, Clone, Copy)]
+pub struct UsageRecord {
+    pub pid: usize,
+    pub group: Option<(usize, usize)>,
+    pub usage: Usage,
+}
+
+/// One line prefixed with `USAGE`
+impl fmt::Display for UsageRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.group {
+            Some((gid, replica)) => write!(
+                f,
+                "USAGE pid={} gid={} replica={} {}",
+                self.pid, gid, replica, self.usage
+            ),
+            None => write!(f, "USAGE pid={} {}", self.pid, self.usage),
+        }
+    }
+}
+
+/// Largest stack a spawned process may ask for, in pages
+pub const MAX_STACK_PAGES: usize = 64;
+
+/// `spawn` flags: the replication degree is held in the low byte, `0` and
+/// `1` both start a single, unreplicated process
+pub const SPAWN_DEGREE_MASK: usize = 0xff;
+/// `spawn` flag: replicate in time rather than in space
+pub const SPAWN_TEMPORAL: usize = 1 << 8;
+
+/// Size in bytes of the kernel's .data and .bss sections, every process runs
+/// on a private copy of them
+fn data_size() -> usize {
+    unsafe { arch::isa::page::align_val(consts::BSS_END - consts::DATA_START, 12) }
+}
+
+/// Copy of a process's frame, stack and data, taken at a vote point
+pub struct Checkpoint {
+    frame: arch::isa::trap::TrapFrame,
+    stack: *mut u8,
+    stack_pages: usize,
+    data: ProcessData,
+    data_pages: *mut u8,
+}
+
+impl Checkpoint {
+    /// Allocates a checkpoint for processes with `stack_pages` of stack,
+    /// `None` if there is not enough memory left
+    pub fn new(stack_pages: usize) -> Option<Self> {
+        let cp = Checkpoint {
+            frame: arch::isa::trap::TrapFrame::zero(),
+            stack: arch::mem::alloc(stack_pages),
+            stack_pages,
+            data: ProcessData::zero(),
+            data_pages: arch::mem::alloc(data_size() / arch::isa::page::PAGE_SIZE),
+        };
+
+        if cp.stack.is_null() || cp.data_pages.is_null() {
+            return None;
+        }
+        Some(cp)
+    }
+
+    pub fn get_pc(&self) -> usize {
+        self.frame.pc
+    }
+}
+
+impl fmt::Debug for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Checkpoint at pc 0x{:x}, stack copy {:p}",
+            self.frame.pc, self.stack
+        )
+    }
+}
+
+impl Drop for Checkpoint {
+    fn drop(&mut self) {
+        if !self.stack.is_null() {
+            arch::mem::dealloc(self.stack);
+        }
+        if !self.data_pages.is_null() {
+            arch::mem::dealloc(self.data_pages);
+        }
+    }
+}
 
 impl fmt::Display for Process {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -57,219 +357,832 @@ impl fmt::Display for Process {
 fn do_nothing() {}
 
 impl Process {
+    /// Allocates a process with its own frame, `stack_pages` of stack, data
+    /// and address space
+    ///
+    /// The process's pid is reserved in the process table, it is `0` if there
+    /// is no table yet. Returns `None`, with the pid released, if there is
+    /// not enough memory left.
+    fn blank(stack_pages: usize) -> Option<Self> {
+        let mut proc = Process::bare(stack_pages)?;
+
+        proc.data_pages = arch::mem::alloc(data_size() / arch::isa::page::PAGE_SIZE);
+        proc.root = slab::zalloc();
+        if proc.data_pages.is_null() || proc.root.is_null() {
+            release_pid(proc.pid);
+            return None;
+        }
+
+        proc.load_data();
+        proc.map_address_space();
+        Some(proc)
+    }
+
+    /// Allocates a process with its own frame and `stack_pages` of stack,
+    /// sharing the kernel's data and without a page table of its own
+    ///
+    /// Returns `None`, with the pid released, if there is not enough memory
+    /// left.
+    fn bare(stack_pages: usize) -> Option<Self> {
+        let proc = Process {
+            group: None,
+            replica: 0,
+            parent: None,
+            priority: 0,
+            deadline: None,
+            exit_status: None,
+            waited_by: None,
+            handler: 0,
+            pending: 0,
+            stopped: false,
+            signal_frame: null_mut(),
+            frame: slab::zalloc(),
+            stack: arch::mem::alloc(stack_pages),
+            stack_pages,
+            pid: unsafe { PROCESS_TABLE.as_mut().map_or(0, |pt| pt.reserve()) },
+            root: null_mut(),
+            state: State::Running,
+            data: ProcessData::zero(),
+            data_pages: null_mut(),
+            program: null_mut(),
+            sleep_until: 0,
+            kernel: false,
+            usage: Usage::zero(),
+        };
+
+        // whatever was allocated is freed when `proc` is dropped
+        if proc.frame.is_null() || proc.stack.is_null() {
+            release_pid(proc.pid);
+            return None;
+        }
+
+        unsafe {
+            (*proc.frame).qm = consts::DEFAULT_QUANTUM as usize;
+        }
+        Some(proc)
+    }
+
+    /// Fills the process's data pages with the kernel's .data and .bss
+    fn load_data(&mut self) {
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                consts::DATA_START as *const u8,
+                self.data_pages,
+                data_size(),
+            );
+        }
+    }
+
+    /// Maps the process's Sv39 address space
+    ///
+    /// Kernel code and read-only data are shared and mapped read-only, while
+    /// .data, .bss and the stack are backed by pages private to the process.
+    /// .data and .bss keep their kernel addresses, the stack lives at
+    /// `consts::STACK_ADDR`.
+    fn map_address_space(&mut self) {
+        let root = unsafe { &mut *self.root };
+
+        unsafe {
+            arch::mem::map_range(
+                root,
+                consts::TEXT_START,
+                consts::TEXT_START,
+                consts::TEXT_END - consts::TEXT_START,
+                PageBits::UserReadExecute,
+            );
+            arch::mem::map_range(
+                root,
+                consts::RODATA_START,
+                consts::RODATA_START,
+                consts::RODATA_END - consts::RODATA_START,
+                PageBits::UserRead,
+            );
+            arch::mem::map_range(
+                root,
+                consts::DATA_START,
+                self.data_pages as usize,
+                data_size(),
+                PageBits::UserReadWrite,
+            );
+        }
+        arch::mem::map_range(
+            root,
+            consts::STACK_ADDR,
+            self.stack as usize,
+            self.stack_size(),
+            PageBits::UserReadWrite,
+        );
+    }
+
+    /// Checksum of the fields the kernel relies on, see `harden`
+    #[cfg(feature = "hardened")]
+    pub fn checksum(&self) -> u64 {
+        let mut sum = crate::harden::Checksum::new();
+
+        sum.write_usize(self.pid);
+        sum.write_usize(self.state.clone() as usize);
+        sum.write_usize(self.group.map(|gid| gid + 1).unwrap_or(0));
+        sum.write_usize(self.replica);
+        sum.write_usize(self.priority);
+        sum.write_usize(self.deadline.map(|d| d as usize).unwrap_or(0));
+        sum.write_usize(self.exit_status.map(|s| s.wrapping_add(1)).unwrap_or(0));
+        sum.write_usize(self.waited_by.map(|pid| pid + 1).unwrap_or(0));
+        sum.write_usize(self.handler);
+        sum.write_usize(self.pending);
+        sum.write_usize(self.stopped as usize);
+        sum.write_usize(self.signal_frame as usize);
+        sum.write_usize(self.parent.map(|pid| pid + 1).unwrap_or(0));
+        sum.write_usize(self.stack as usize);
+        sum.write_usize(self.stack_pages);
+        sum.write_usize(self.frame as usize);
+        sum.write_usize(self.root as usize);
+        sum.write_usize(self.data_pages as usize);
+        sum.write_usize(self.sleep_until as usize);
+        sum.write_usize(self.kernel as usize);
+        sum.write(&self.data.cwd_path);
+        sum.finish()
+    }
+
+    /// What the scheduler needs to know about this process
+    pub fn task(&self) -> sched::Task {
+        sched::Task {
+            pid: self.pid,
+            priority: self.priority,
+            deadline: self.deadline,
+        }
+    }
+
+    /// Value of the address translation register for this process
+    fn satp(&self) -> usize {
+        arch::mem::build_satp(unsafe { &*self.root }, self.pid)
+    }
+
+    pub fn usage_record(&self) -> UsageRecord {
+        UsageRecord {
+            pid: self.pid,
+            group: self.group.map(|gid| (gid, self.replica)),
+            usage: self.usage,
+        }
+    }
+
     pub fn get_frame_addr(&self) -> usize {
         self.frame as usize
     }
     pub fn get_pc(&self) -> usize {
         unsafe { (*self.frame).pc }
     }
+    pub fn get_stack_addr(&self) -> usize {
+        self.stack as usize
+    }
+    pub fn get_stack_pages(&self) -> usize {
+        self.stack_pages
+    }
+    /// Size in bytes of the process's stack
+    pub fn stack_size(&self) -> usize {
+        self.stack_pages * arch::isa::page::PAGE_SIZE
+    }
     pub fn get_table_addr(&self) -> usize {
         self.root as usize
     }
     pub fn get_state(&self) -> &State {
         &self.state
     }
-    pub fn get_pid(&self) -> usize {
-       self.pid
-    }
-    
-    pub fn clones(&self) -> Self {
-        let cloned = Process {
-            is_tmr: false,
-            frame: arch::mem::zalloc(1) as *mut arch::isa::trap::TrapFrame,
-            stack: arch::mem::alloc(consts::STACK_PAGES),
-            pid: unsafe { NEXT_PID },
-            root: arch::mem::zalloc(1) as *mut arch::isa::page::Table,
-            state: State::Running,
-            data: ProcessData::zero(),
-            program: null_mut(),
-            sleep_until: 0,
-        };
-    
+    pub fn get_quantum(&self) -> u16 {
+        unsafe { (*self.frame).qm as u16 }
+    }
+
+    /// Lets the process run for `qm` times `consts::CONTEXT_SWITCH_TIME`
+    /// before it is preempted
+    pub fn set_quantum(&mut self, qm: u16) {
         unsafe {
-            NEXT_PID += 1;
+            (*self.frame).qm = qm.max(1) as usize;
         }
-    
-    cloned
     }
 
-    pub fn tmr(&self) -> Option<(Self, Self, Self)> {
-        if self.is_tmr {
-            let clone1 = self.clones();
-            let clone2 = self.clones();
-            let clone3 = self.clones();
-            Some((clone1, clone2, clone3))
-        } else {
-            None
-        }
+    pub fn get_pid(&self) -> usize {
+        self.pid
+    }
+    pub fn is_kernel(&self) -> bool {
+        self.kernel
+    }
+    pub fn is_replica(&self) -> bool {
+        self.group.is_some()
+    }
+    pub fn is_zombie(&self) -> bool {
+        matches!(self.state, State::Dead)
+    }
+    pub fn get_exit_status(&self) -> Option<usize> {
+        self.exit_status
     }
-    
 
-    pub fn sleep(&mut self, duration: usize) {
-        self.state = State::Sleeping;
-        self.sleep_until = duration;
+    pub fn get_sleep_until(&self) -> u64 {
+        self.sleep_until
     }
-}
 
+    /// Puts the process to sleep until `mtime` reaches `until`
+    pub fn sleep(&mut self, until: u64) {
+        self.state = State::Sleeping;
+        self.sleep_until = until;
+    }
 
-pub fn create_process(func: fn(), tmr: bool) -> usize {
-    let func_addr = func as usize;
-    let func_vaddr = func_addr; //- 0x6000_0000;
+    /// Stops scheduling the process until `resume`, leaving its state as it
+    /// is
+    pub fn stop(&mut self) {
+        if !self.is_zombie() {
+            self.stopped = true;
+        }
+    }
 
-    let mut pid = 0;
-
-    let ret_proc = Process {
-        is_tmr: tmr,
-        frame: arch::mem::zalloc(1) as *mut arch::isa::trap::TrapFrame,
-        stack: arch::mem::alloc(consts::STACK_PAGES),
-        pid: unsafe { NEXT_PID },
-        root: arch::mem::zalloc(1) as *mut arch::isa::page::Table,
-        state: State::Running,
-        data: ProcessData::zero(),
-        program: null_mut(),
-        sleep_until: 0,
-    };
+    /// Lets a stopped process be scheduled again once its state allows it
+    pub fn resume(&mut self) {
+        self.stopped = false;
+    }
 
-    if(!ret_proc.is_tmr) {
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
 
-        unsafe {
-            NEXT_PID += 1;
+    /// Makes the process runnable again with `status` as what `wait` returns
+    ///
+    /// A handler delivered while the process waited runs first, the status
+    /// goes to the context it interrupted.
+    pub fn wake_from_wait(&mut self, status: usize) {
+        if !matches!(self.state, State::Waiting) {
+            return;
         }
+        let frame = if self.signal_frame.is_null() {
+            self.frame
+        } else {
+            self.signal_frame
+        };
 
-        arch::frame::create_process(
-            unsafe { &mut *ret_proc.frame },
-            func_vaddr,
-            ret_proc.stack as usize,
-            do_nothing as usize,
-            ret_proc.pid as usize,
-        );
+        self.state = State::Running;
+        unsafe { (*frame).set_return(status) };
+    }
 
-        if let Some(mut pl) = unsafe { PROCESS_LIST.take() } {
-            let pid = ret_proc.pid;
-            pl.push_back(ret_proc);
+    /// Stops scheduling the process until its replica group votes
+    pub fn await_vote(&mut self) {
+        if let State::Running = self.state {
+            self.state = State::Voting;
+        }
+    }
 
-            unsafe {
-                PROCESS_LIST.replace(pl);
-            }
+    /// Makes a process blocked in `await_vote` runnable again
+    pub fn end_vote(&mut self) {
+        if let State::Voting = self.state {
+            self.state = State::Running;
+        }
+    }
+
+    /// Sets the handler run on `Signal::User`, `0` removes it
+    pub fn set_handler(&mut self, handler: usize) {
+        self.handler = handler;
+    }
 
+    /// Points the process at `trampoline` to run its handler for `signal`
+    ///
+    /// The interrupted context is saved until `leave_handler`, a signal
+    /// arriving while the handler runs is kept pending. Returns `false` if the
+    /// process has no handler.
+    pub fn deliver(&mut self, trampoline: usize, signal: Signal) -> bool {
+        if self.handler == 0 {
+            return false;
+        }
+        if !self.signal_frame.is_null() {
+            self.pending |= 1 << signal as usize;
+            return true;
         }
 
-    } else {
-    
-    let mut processes: Option<VecDeque<Process>> = None;
+        self.signal_frame = slab::zalloc();
+        unsafe {
+            *self.signal_frame = *self.frame;
 
+            let frame = &mut *self.frame;
+            frame.pc = trampoline;
+            frame.set_arg(0, self.handler);
+            frame.set_arg(1, signal as usize);
+        }
+        true
+    }
+
+    /// Resumes the context the running handler interrupted, then delivers the
+    /// next pending signal
+    ///
+    /// Returns `false` if the process is not running a handler.
+    pub fn leave_handler(&mut self, trampoline: usize) -> bool {
+        if self.signal_frame.is_null() {
+            return false;
+        }
 
         unsafe {
-            TMR_BOOL = true;
-
-            processes = Some(VecDeque::with_capacity(3));
-        
-            let tmr_values_list = ret_proc.tmr();
-            for (p1, p2, p3) in tmr_values_list {
-                processes.as_mut().unwrap().push_back(p1);
-                processes.as_mut().unwrap().push_back(p2);
-                processes.as_mut().unwrap().push_back(p3);
-            }
-            
+            let saved = &*self.signal_frame;
+            let frame = &mut *self.frame;
+
+            frame.regs = saved.regs;
+            frame.fregs = saved.fregs;
+            frame.pc = saved.pc;
+        }
+        slab::free(self.signal_frame);
+        self.signal_frame = null_mut();
+
+        let user = 1 << Signal::User as usize;
+        if self.pending & user != 0 {
+            self.pending &= !user;
+            self.deliver(trampoline, Signal::User);
         }
-        
-        for process in processes.as_mut().unwrap() {
+        true
+    }
+
+    /// Restarts the process at `entry` with a zeroed frame and stack and a
+    /// fresh copy of the kernel's data
+    pub fn rewind(&mut self, entry: usize) {
+        let satp = self.satp();
+
+        unsafe {
+            core::ptr::write_bytes(self.stack, 0, self.stack_size());
+
+            let frame = &mut *self.frame;
+            frame.regs = [0; 32];
+            frame.fregs = [0; 32];
             arch::frame::create_process(
-                unsafe { &mut *process.frame },
-                func_vaddr,
-                process.stack as usize,
+                frame,
+                entry,
+                consts::STACK_ADDR + self.stack_size(),
                 do_nothing as usize,
-                process.pid as usize,
+                self.pid,
+                satp,
             );
+        }
+        self.data = ProcessData::zero();
+        self.load_data();
+    }
 
-            if let Some(mut pl) = unsafe { PROCESS_LIST.take() } {
-                pid = (*process).pid;
-                pl.push_back(process.clone());
+    /// Saves the process's registers, stack and data into `cp`
+    ///
+    /// Only as much stack as both the process and `cp` hold is copied,
+    /// replicas of a group always have stacks of the same size.
+    pub fn save(&self, cp: &mut Checkpoint) {
+        let stack_size = self.stack_pages.min(cp.stack_pages) * arch::isa::page::PAGE_SIZE;
 
-                unsafe {
-                    PROCESS_LIST.replace(pl);
-                }
-                
+        unsafe {
+            cp.frame = *self.frame;
+            core::ptr::copy_nonoverlapping(self.stack, cp.stack, stack_size);
+            core::ptr::copy_nonoverlapping(self.data_pages, cp.data_pages, data_size());
+        }
+        cp.data = self.data.clone();
+    }
+
+    /// Rolls the process back to `cp`, which may have been taken from a
+    /// sibling replica
+    ///
+    /// The process keeps its own pid, page table and mode. Every process maps
+    /// its stack and data at the same virtual addresses, so registers and
+    /// pointers saved in the checkpoint stay valid.
+    pub fn restore(&mut self, cp: &Checkpoint) {
+        let stack_size = self.stack_pages.min(cp.stack_pages) * arch::isa::page::PAGE_SIZE;
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(cp.stack, self.stack, stack_size);
+            core::ptr::copy_nonoverlapping(cp.data_pages, self.data_pages, data_size());
+
+            let frame = &mut *self.frame;
+            frame.regs = cp.frame.regs;
+            frame.fregs = cp.frame.fregs;
+            frame.pc = cp.frame.pc;
+        }
+        self.data = cp.data.clone();
+    }
+}
+
+/// Sets up `proc`'s frame to start at `func_addr`, adds it to the process
+/// table and hands it to the scheduler
+fn start_process(proc: Process, func_addr: usize) -> usize {
+    arch::frame::create_process(
+        unsafe { &mut *proc.frame },
+        func_addr,
+        consts::STACK_ADDR + proc.stack_size(),
+        do_nothing as usize,
+        proc.pid as usize,
+        proc.satp(),
+    );
+
+    admit(proc)
+}
+
+/// Adds `proc` to the process table and hands it to the scheduler, returns
+/// its pid or `0` if it could not be added
+fn admit(proc: Process) -> usize {
+    let pid = proc.pid;
+    let task = proc.task();
+
+    let inserted = unsafe {
+        PROCESS_TABLE.as_mut().map_or(false, |pt| {
+            if pt.insert(proc) {
+                true
             } else {
-                return 0;
-            }
-            unsafe {
-                NEXT_PID += 1;
+                pt.release(pid);
+                false
             }
-        
-        }
-        
-    }   
+        })
+    };
+    if !inserted {
+        return 0;
+    }
+
+    sched::enqueue(task);
     pid
 }
 
+/// Starts a kernel thread at `pc` with `arg` in a0 and `stack_pages` of
+/// stack, returns its pid or `0` if it could not be started
+///
+/// Kernel threads run in supervisor mode in the kernel page table, they
+/// share the kernel's memory and are scheduled like any process. They do not
+/// keep the machine running, see `exit`.
+pub fn start_kernel_thread(pc: usize, arg: usize, stack_pages: usize) -> usize {
+    let mut proc = match Process::bare(stack_pages) {
+        Some(proc) => proc,
+        None => return 0,
+    };
+    proc.kernel = true;
 
-pub fn sleep_pid(pid: usize, duration: usize) -> bool {
-    unsafe {
-        if let Some(mut pl) = PROCESS_LIST.take() {
-            for proc in pl.iter_mut() {
-                if proc.pid == pid {
-                    proc.sleep(duration);
-                    return true;
-                }
+    let frame = unsafe { &mut *proc.frame };
+    arch::frame::create_kernel_task(frame, pc, proc.stack as usize + proc.stack_size(), proc.pid);
+    frame.set_arg(0, arg);
+
+    admit(proc)
+}
+
+pub fn create_process(func: fn()) -> usize {
+    let func_addr = func as usize;
+    let func_vaddr = func_addr; //- 0x6000_0000;
+
+    match Process::blank(consts::STACK_PAGES) {
+        Some(proc) => start_process(proc, func_vaddr),
+        None => 0,
+    }
+}
+
+/// Creates a replica group running `func` `degree` times
+///
+/// In `Mode::Spatial` each replica runs as its own process, in
+/// `Mode::Temporal` a single process runs `func` `degree` times in a row.
+/// Returns `None` if `degree` is zero or if the group could not be created.
+pub fn create_replicated(
+    func: fn(),
+    degree: usize,
+    mode: replica::Mode,
+) -> Option<replica::GroupHandle> {
+    start_replicated(func as usize, consts::STACK_PAGES, degree, mode, None)
+        .map(|(handle, _)| handle)
+}
+
+/// Creates a replica group running `entry` and returns it with the pid of
+/// its first replica
+///
+/// If a replica cannot be started, the group and the replicas already
+/// started are torn down.
+fn start_replicated(
+    entry: usize,
+    stack_pages: usize,
+    degree: usize,
+    mode: replica::Mode,
+    parent: Option<usize>,
+) -> Option<(replica::GroupHandle, usize)> {
+    let handle = replica::create_group(degree, entry, mode)?;
+    let mut started = Vec::with_capacity(handle.replicas());
+
+    for _ in 0..handle.replicas() {
+        match start_replica(&handle, entry, stack_pages, parent) {
+            Some(pid) => started.push(pid),
+            None => {
+                abandon(handle.gid, &started);
+                return None;
             }
         }
     }
-    false
+
+    started.first().map(|&pid| (handle, pid))
 }
 
-impl Drop for Process {
-    fn drop(&mut self) {
-        arch::mem::dealloc(self.stack);
+/// Starts a replica of the group `handle` and returns its pid
+fn start_replica(
+    handle: &replica::GroupHandle,
+    entry: usize,
+    stack_pages: usize,
+    parent: Option<usize>,
+) -> Option<usize> {
+    let mut proc = Process::blank(stack_pages)?;
+    proc.parent = parent;
+    proc.group = Some(handle.gid);
+    proc.replica = match replica::join(handle.gid, proc.pid) {
+        Some(replica) => replica,
+        None => {
+            release_pid(proc.pid);
+            return None;
+        }
+    };
+    proc.deadline = replica::with_group(handle.gid, |g| g.deadline());
 
-        unsafe {
-            arch::mem::unmap(&mut *self.root);
+    match start_process(proc, entry) {
+        0 => None,
+        pid => Some(pid),
+    }
+}
+
+/// Frees `pid`, reserved by a process that never made it into the table
+fn release_pid(pid: usize) {
+    unsafe {
+        if let Some(pt) = PROCESS_TABLE.as_mut() {
+            pt.release(pid);
         }
+    }
+}
 
-        arch::mem::dealloc(self.root as *mut u8);
+/// Removes the group `gid`, whose replicas could not all be started, and the
+/// replicas in `started`
+fn abandon(gid: usize, started: &[usize]) {
+    for &pid in started {
+        sched::dequeue(pid);
+        unsafe { PROCESS_TABLE.as_mut().and_then(|pt| pt.remove(pid)) };
     }
+    replica::remove_group(gid);
+}
+
+/// Whether `addr` points into kernel code, which every process maps
+pub fn in_text(addr: usize) -> bool {
+    unsafe { addr >= consts::TEXT_START && addr < consts::TEXT_END }
 }
 
-pub fn init_tmr_values_list() {
+/// Starts a child of `parent` at `entry`, with `stack_pages` of stack, or
+/// `consts::STACK_PAGES` if zero
+///
+/// `flags` holds the replication degree, masked by `SPAWN_DEGREE_MASK`, and
+/// `SPAWN_TEMPORAL`. Every replica of a replicated child is a child of
+/// `parent`, the pid of the first one is returned. Returns `None` if `entry`
+/// is not kernel code or the stack is too large.
+pub fn spawn(parent: usize, entry: usize, stack_pages: usize, flags: usize) -> Option<usize> {
+    if !in_text(entry) || stack_pages > MAX_STACK_PAGES {
+        return None;
+    }
+
+    let stack_pages = if stack_pages == 0 {
+        consts::STACK_PAGES
+    } else {
+        stack_pages
+    };
+    let degree = flags & SPAWN_DEGREE_MASK;
+
+    if degree <= 1 {
+        let mut proc = Process::blank(stack_pages)?;
+        proc.parent = Some(parent);
+
+        return match start_process(proc, entry) {
+            0 => None,
+            pid => Some(pid),
+        };
+    }
+
+    let mode = if flags & SPAWN_TEMPORAL != 0 {
+        replica::Mode::Temporal
+    } else {
+        replica::Mode::Spatial
+    };
+    start_replicated(entry, stack_pages, degree, mode, Some(parent)).map(|(_, pid)| pid)
+}
+
+/// Pids of the children of `pid`, zombies included
+pub fn children(pid: usize) -> Vec<usize> {
     unsafe {
-        TMR_VALUES_LIST = Some(VecDeque::with_capacity(3));
+        PROCESS_TABLE
+            .as_ref()
+            .map(|pt| {
+                pt.iter()
+                    .filter(|p| p.parent == Some(pid))
+                    .map(|p| p.pid)
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 }
 
-pub fn init() -> usize {
+/// Runs `f` on the process `pid`, if it exists
+pub fn with_process<R>(pid: usize, f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+    unsafe { PROCESS_TABLE.as_mut()?.get_mut(pid).map(f) }
+}
+
+/// Applies `f` to `pid` and hands the updated process to the scheduler
+fn reschedule(pid: usize, f: impl FnOnce(&mut Process)) -> bool {
+    let task = with_process(pid, |p| {
+        f(p);
+        p.task()
+    });
+
+    match task {
+        Some(task) => {
+            sched::dequeue(pid);
+            sched::enqueue(task);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn set_quantum(pid: usize, qm: u16) -> bool {
+    with_process(pid, |p| p.set_quantum(qm)).is_some()
+}
+
+pub fn set_priority(pid: usize, priority: usize) -> bool {
+    reschedule(pid, |p| p.priority = priority)
+}
+
+pub fn set_deadline(pid: usize, deadline: Option<u64>) -> bool {
+    reschedule(pid, |p| p.deadline = deadline)
+}
+
+/// Gives `pid` a deadline `ms` milliseconds from now, or none if `ms` is zero
+///
+/// Returns `false` if there is no such process or it is a replica, whose
+/// deadline is set by its group's watchdog.
+pub fn set_deadline_in(pid: usize, ms: usize) -> bool {
+    if group_of(pid).is_some() {
+        return false;
+    }
+
+    let deadline = match ms {
+        0 => None,
+        ms => Some(sched::get_mtime().wrapping_add(ms as u64 * consts::FREQ / 1000)),
+    };
+    set_deadline(pid, deadline)
+}
+
+/// Outcome of `wait`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wait {
+    /// The process had exited with this status and was reaped
+    Exited(usize),
+    /// The process is still alive, the waiter blocks until it exits
+    Blocked,
+    NoSuchProcess,
+}
+
+/// Terminates `pid` with `status`
+///
+/// The process stays in the process table as a zombie until it is reaped by
+/// `wait`. If a process already waits on it, the waiter is woken up with
+/// `status` and the zombie is reaped right away. With `close-when-done`, the
+/// machine shuts down once no process is left alive, kernel threads aside.
+pub fn exit(pid: usize, status: usize) {
+    let exited = with_process(pid, |p| {
+        p.state = State::Dead;
+        p.exit_status = Some(status);
+    });
+    if exited.is_none() {
+        return;
+    }
+    sched::dequeue(pid);
+    sched::remove_sleeper(pid);
+    if let Some((gid, replica)) = group_of(pid) {
+        replica::leave(gid, replica);
+    }
+    crate::println!("Process {} exited with status {}", pid, status as isize);
+
+    // nobody is left to wait on the children, orphans are reaped as they
+    // exit
+    for child in children(pid) {
+        if with_process(child, |p| p.is_zombie()) == Some(true) {
+            reap(child);
+        } else {
+            with_process(child, |p| {
+                p.parent = None;
+                p.waited_by = None;
+            });
+        }
+    }
+
+    if let Some(waiter) = with_process(pid, |p| p.waited_by.take()).flatten() {
+        with_process(waiter, |p| p.wake_from_wait(status));
+        reap(pid);
+    } else if with_process(pid, |p| p.parent.is_none()) == Some(true) {
+        reap(pid);
+    }
+
+    #[cfg(feature = "close-when-done")]
+    if live_count() == 0 {
+        crate::println!("No process left, shutting down.");
+        crate::exit_qemu_as_success();
+    }
+}
+
+/// Removes the zombie `pid` from the process table, freeing its memory, and
+/// returns its exit status
+///
+/// Its usage is kept by `stats` for the final report.
+fn reap(pid: usize) -> Option<usize> {
+    let pt = unsafe { PROCESS_TABLE.as_mut()? };
+    if !pt.get(pid)?.is_zombie() {
+        return None;
+    }
+    let zombie = pt.remove(pid)?;
+
+    stats::record_exit(zombie.usage_record());
+    Some(zombie.exit_status.unwrap_or(KILLED))
+}
+
+/// Collects the exit status of the child `pid` on behalf of its parent
+/// `waiter`
+///
+/// A zombie is reaped right away. Otherwise `waiter` sleeps in
+/// `State::Waiting` until `pid` exits, its frame then returns the status.
+pub fn wait(waiter: usize, pid: usize) -> Wait {
+    match with_process(pid, |p| (p.parent == Some(waiter), p.is_zombie())) {
+        Some((false, _)) => Wait::NoSuchProcess,
+        Some((true, true)) => reap(pid).map_or(Wait::NoSuchProcess, Wait::Exited),
+        Some((true, false)) => {
+            with_process(pid, |p| p.waited_by = Some(waiter));
+            with_process(waiter, |p| p.state = State::Waiting);
+            Wait::Blocked
+        }
+        None => Wait::NoSuchProcess,
+    }
+}
+
+/// Number of processes that have not exited, kernel threads excluded
+pub fn live_count() -> usize {
     unsafe {
-        let start_time = mcycle::read();
+        PROCESS_TABLE
+            .as_ref()
+            .map(|pt| pt.iter().filter(|p| !p.is_zombie() && !p.kernel).count())
+            .unwrap_or(0)
+    }
+}
+
+/// Returns the replica group and replica id of `pid`
+pub fn group_of(pid: usize) -> Option<(usize, usize)> {
+    with_process(pid, |p| p.group.map(|gid| (gid, p.replica))).flatten()
+}
 
-        PROCESS_LIST = Some(VecDeque::with_capacity(15));
+/// Puts `pid` to sleep for `ms` milliseconds
+///
+/// Returns `false` if there is no such process.
+pub fn sleep_pid(pid: usize, ms: usize) -> bool {
+    let until = sched::get_mtime().wrapping_add(ms as u64 * consts::FREQ / 1000);
 
-        init_tmr_values_list();
+    if with_process(pid, |p| p.sleep(until)).is_none() {
+        return false;
+    }
+    sched::add_sleeper(pid, until);
+    true
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        if !self.stack.is_null() {
+            arch::mem::dealloc(self.stack);
+        }
+        // kernel threads share the kernel's data
+        if !self.data_pages.is_null() {
+            arch::mem::dealloc(self.data_pages);
+        }
+        slab::free(self.frame);
+        slab::free(self.signal_frame);
 
-        create_process(sum, true);
+        if !self.root.is_null() {
+            unsafe {
+                arch::mem::unmap(&mut *self.root);
+            }
+            slab::free(self.root);
+        }
+    }
+}
 
-        let pl = PROCESS_LIST.take().unwrap();
-        let p = pl.front().unwrap().frame;
+/// Prints the resources used by every process, one `USAGE` line each
+pub fn print_usage() {
+    if let Some(pt) = unsafe { PROCESS_TABLE.as_ref() } {
+        for proc in pt.iter() {
+            crate::println!("{}", proc.usage_record());
+        }
+    }
+}
 
-        PROCESS_LIST.replace(pl);
+pub fn init() -> usize {
+    unsafe {
+        PROCESS_TABLE = Some(ProcessTable::new());
 
-        let end_time = mcycle::read();
-        let (execution_time, _) = end_time.overflowing_sub(start_time);
+        replica::init();
 
-        const CLOCK_FREQUENCY: f32 = 100_000_000.0; // 100 MHz
-        let execution_time_sec = (execution_time as f32) / CLOCK_FREQUENCY;
+        create_replicated(sum, 3, replica::Mode::Spatial);
 
-        time_total = execution_time_sec * 1000.0;
+        let p = PROCESS_TABLE.as_ref().unwrap().iter().next().unwrap().frame;
         (*p).pc
     }
-
 }
 
 fn sum() {
     syscall::syscall_sum();
 }
 
-
 fn init_process() {
     syscall::syscall_dump();
-    syscall::syscall_exit();
+    syscall::syscall_exit(0);
 }
-  
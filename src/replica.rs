@@ -0,0 +1,710 @@
+use crate::process::{self, Checkpoint};
+use crate::signal::{self, Signal};
+use crate::{consts, fault, sched, stats};
+use alloc::collections::vec_deque::VecDeque;
+use alloc::vec::Vec;
+use core::fmt;
+use riscv::register::cycle;
+
+static mut NEXT_GID: usize = 1;
+
+/// How a group replicates its task
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// `degree` processes run the task concurrently
+    Spatial,
+    /// A single process runs the task `degree` times in a row, its frame and
+    /// stack are reset between runs
+    Temporal,
+}
+
+/// Handle to a group of replicas created by `process::create_replicated`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GroupHandle {
+    pub gid: usize,
+    pub degree: usize,
+    pub mode: Mode,
+}
+
+impl GroupHandle {
+    /// How many processes the group is made of
+    pub fn replicas(&self) -> usize {
+        match self.mode {
+            Mode::Spatial => self.degree,
+            Mode::Temporal => 1,
+        }
+    }
+}
+
+/// Outcome of voting on the results of a replica group
+#[derive(Debug, Clone, PartialEq)]
+pub enum Vote {
+    /// Every replica produced the same value
+    Unanimous(usize),
+    /// More than half of the replicas produced `value`, `dissenters` holds
+    /// the ids of the replicas that disagreed or produced nothing, or the
+    /// runs that did in temporal mode
+    Majority {
+        value: usize,
+        dissenters: Vec<usize>,
+    },
+    /// No value was produced by more than half of the replicas
+    NoMajority,
+}
+
+impl Vote {
+    /// The value agreed by the majority, if any
+    pub fn value(&self) -> Option<usize> {
+        match self {
+            Vote::Unanimous(value) | Vote::Majority { value, .. } => Some(*value),
+            Vote::NoMajority => None,
+        }
+    }
+}
+
+impl fmt::Display for Vote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Vote::Unanimous(value) => write!(f, "unanimous value {}", value),
+            Vote::Majority { value, dissenters } => write!(
+                f,
+                "majority value {}, replica(s) {:?} disagreed",
+                value, dissenters
+            ),
+            Vote::NoMajority => write!(f, "no majority"),
+        }
+    }
+}
+
+/// Folds the checkpoint `id` into the control-flow signature `signature`
+///
+/// The signature depends on the order checkpoints are reached in, so a
+/// replica skipping, repeating or reordering checkpoints ends up with a
+/// different signature than its siblings.
+pub fn sign(signature: usize, id: usize) -> usize {
+    signature.rotate_left(5) ^ id
+}
+
+/// Merges the votes on the results and on the control-flow signatures
+///
+/// Replicas whose signature disagrees are added to the dissenters even if
+/// their result agrees. Without a majority on either the results or the
+/// control flow no replica can be trusted, so neither vote has a majority.
+pub fn merge(outcome: Vote, flow: &Vote) -> Vote {
+    let (value, mut dissenters) = match (outcome, flow) {
+        (_, Vote::NoMajority) | (Vote::NoMajority, _) => return Vote::NoMajority,
+        (Vote::Unanimous(value), _) => (value, Vec::new()),
+        (Vote::Majority { value, dissenters }, _) => (value, dissenters),
+    };
+
+    if let Vote::Majority {
+        dissenters: diverged,
+        ..
+    } = flow
+    {
+        for replica in diverged.iter() {
+            if !dissenters.contains(replica) {
+                dissenters.push(*replica);
+            }
+        }
+        dissenters.sort_unstable();
+    }
+
+    if dissenters.is_empty() {
+        Vote::Unanimous(value)
+    } else {
+        Vote::Majority { value, dissenters }
+    }
+}
+
+/// Majority voter
+///
+/// `results` holds one slot per replica, indexed by replica id. Empty slots
+/// count against every value.
+pub fn vote(results: &[Option<usize>]) -> Vote {
+    for candidate in results.iter().flatten() {
+        let agree = results.iter().filter(|r| **r == Some(*candidate)).count();
+
+        if agree * 2 > results.len() {
+            let dissenters: Vec<usize> = results
+                .iter()
+                .enumerate()
+                .filter(|(_, r)| **r != Some(*candidate))
+                .map(|(replica, _)| replica)
+                .collect();
+
+            if dissenters.is_empty() {
+                return Vote::Unanimous(*candidate);
+            }
+            return Vote::Majority {
+                value: *candidate,
+                dissenters,
+            };
+        }
+    }
+    Vote::NoMajority
+}
+
+/// A set of processes executing the same task
+///
+/// Replica ids go from `0` to `degree - 1` and index both `members`, which
+/// holds the pid of each replica, and `results`, which holds the value each
+/// replica submitted for the current vote, and `checkpoints`, which holds
+/// the state of each replica when it submitted.
+///
+/// In temporal mode the group has a single member, replica `0`, and
+/// `results` is indexed by `run` instead.
+///
+/// Each replica folds the checkpoints it reaches into its entry of `flow`,
+/// which is moved to `signatures`, indexed like `results`, when it submits.
+///
+/// The group's watchdog expects every replica that has not failed to submit
+/// before `deadline`, an `mtime` value re-armed `timeout` ticks after every
+/// vote. It is armed once every replica has joined and disarmed when no
+/// replica is left to submit. Replicas that exited count as failed.
+#[derive(Debug)]
+pub struct ReplicaGroup {
+    pub gid: usize,
+    pub degree: usize,
+    pub entry: usize,
+    pub mode: Mode,
+    members: Vec<usize>,
+    results: Vec<Option<usize>>,
+    signatures: Vec<Option<usize>>,
+    flow: Vec<usize>,
+    run: usize,
+    checkpoints: Vec<Option<Checkpoint>>,
+    failed: Vec<bool>,
+    armed: bool,
+    deadline: u64,
+    timeout: u64,
+    /// Roll silent replicas back to a healthy checkpoint instead of failing them
+    restart: bool,
+}
+
+pub static mut REPLICA_GROUPS: Option<VecDeque<ReplicaGroup>> = None;
+
+impl ReplicaGroup {
+    pub fn new(gid: usize, degree: usize, entry: usize, mode: Mode) -> Self {
+        let replicas = match mode {
+            Mode::Spatial => degree,
+            Mode::Temporal => 1,
+        };
+
+        ReplicaGroup {
+            gid,
+            degree,
+            entry,
+            mode,
+            members: Vec::with_capacity(replicas),
+            results: (0..degree).map(|_| None).collect(),
+            signatures: (0..degree).map(|_| None).collect(),
+            flow: (0..replicas).map(|_| 0).collect(),
+            run: 0,
+            checkpoints: (0..replicas).map(|_| None).collect(),
+            failed: (0..replicas).map(|_| false).collect(),
+            armed: false,
+            deadline: 0,
+            timeout: consts::REPLICA_TIMEOUT,
+            restart: true,
+        }
+    }
+
+    pub fn handle(&self) -> GroupHandle {
+        GroupHandle {
+            gid: self.gid,
+            degree: self.degree,
+            mode: self.mode,
+        }
+    }
+
+    pub fn members(&self) -> &[usize] {
+        &self.members
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.members.len() == self.handle().replicas()
+    }
+
+    /// Pid of the replica `replica`, unless it failed or exited
+    pub fn pid_of(&self, replica: usize) -> Option<usize> {
+        if self.is_failed(replica) {
+            return None;
+        }
+        self.members.get(replica).copied()
+    }
+
+    /// Replica id of the process `pid`
+    pub fn replica_of(&self, pid: usize) -> Option<usize> {
+        (0..self.members.len()).find(|&r| self.pid_of(r) == Some(pid))
+    }
+
+    /// Pids of the replicas that have not failed or exited
+    pub fn live_members(&self) -> Vec<usize> {
+        (0..self.members.len())
+            .filter_map(|r| self.pid_of(r))
+            .collect()
+    }
+
+    pub fn results(&self) -> &[Option<usize>] {
+        &self.results
+    }
+
+    /// Checks whether every replica that has not failed submitted its result
+    pub fn has_all_results(&self) -> bool {
+        match self.mode {
+            Mode::Spatial => self
+                .results
+                .iter()
+                .zip(self.failed.iter())
+                .all(|(r, failed)| r.is_some() || *failed),
+            Mode::Temporal => self.results.iter().all(|r| r.is_some()),
+        }
+    }
+
+    pub fn is_failed(&self, replica: usize) -> bool {
+        self.failed.get(replica).copied().unwrap_or(false)
+    }
+
+    /// Sets how many `mtime` ticks replicas have to submit before the
+    /// watchdog expires, and whether silent replicas are restarted
+    pub fn set_watchdog(&mut self, timeout: u64, restart: bool) {
+        self.timeout = timeout;
+        self.restart = restart;
+        if self.armed {
+            self.arm();
+        }
+    }
+
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.armed
+    }
+
+    /// Run under way in temporal mode, whose result goes to the slot of the
+    /// same index, `None` in spatial mode
+    pub fn current_run(&self) -> Option<usize> {
+        match self.mode {
+            Mode::Spatial => None,
+            Mode::Temporal => Some(self.run),
+        }
+    }
+
+    /// Re-arms the watchdog and makes its deadline the replicas' scheduling
+    /// deadline, or disarms it when every replica failed or exited
+    fn arm(&mut self) {
+        let live = self.live_members();
+
+        self.armed = !live.is_empty();
+        self.deadline = sched::get_mtime().wrapping_add(self.timeout);
+        for pid in live {
+            process::set_deadline(pid, Some(self.deadline));
+        }
+    }
+
+    /// Whether the watchdog is armed and its deadline passed by `now`
+    fn has_expired(&self, now: u64) -> bool {
+        self.armed && now.wrapping_sub(self.deadline) as i64 >= 0
+    }
+
+    /// Stores `value` as the result of `replica`, or of the current run in
+    /// temporal mode
+    ///
+    /// A replica submitting twice before a vote overwrites its own slot only.
+    pub fn submit(&mut self, replica: usize, value: usize) {
+        let slot = match self.mode {
+            Mode::Spatial => replica,
+            Mode::Temporal => self.run,
+        };
+        let signature = match self.flow.get_mut(replica) {
+            Some(flow) => core::mem::replace(flow, 0),
+            None => return,
+        };
+
+        if slot < self.results.len() {
+            self.results[slot] = Some(value);
+            self.signatures[slot] = Some(signature);
+        }
+    }
+
+    /// Records that `replica` reached the control-flow checkpoint `id`
+    pub fn sign(&mut self, replica: usize, id: usize) {
+        if let Some(flow) = self.flow.get_mut(replica) {
+            *flow = sign(*flow, id);
+        }
+    }
+
+    pub fn signatures(&self) -> &[Option<usize>] {
+        &self.signatures
+    }
+
+    /// Restarts the temporal replica at the group's entry for its next run
+    fn next_run(&mut self) {
+        self.run += 1;
+
+        let entry = self.entry;
+        if let Some(pid) = self.pid_of(0) {
+            process::with_process(pid, |p| p.rewind(entry));
+        }
+    }
+
+    /// Votes on the submitted results and control-flow signatures
+    pub fn vote(&self) -> Vote {
+        merge(vote(&self.results), &vote(&self.signatures))
+    }
+
+    /// Clears every result slot for the next vote
+    pub fn reset_results(&mut self) {
+        for slot in self.results.iter_mut() {
+            *slot = None;
+        }
+        for slot in self.signatures.iter_mut() {
+            *slot = None;
+        }
+    }
+
+    /// Saves the state of `replica` so a sibling can be rolled back to it
+    pub fn checkpoint(&mut self, replica: usize) {
+        let pid = match self.pid_of(replica) {
+            Some(pid) => pid,
+            None => return,
+        };
+
+        if let Some(slot) = self.checkpoints.get_mut(replica) {
+            process::with_process(pid, |p| {
+                if slot.is_none() {
+                    *slot = Checkpoint::new(p.get_stack_pages());
+                }
+                // without memory for a checkpoint the replica cannot be
+                // recovered from, see `recover`
+                if let Some(cp) = slot {
+                    p.save(cp)
+                }
+            });
+        }
+    }
+
+    /// Rolls back every replica that disagreed with the majority to the
+    /// checkpoint of a replica that agreed
+    ///
+    /// Returns how many replicas were recovered.
+    pub fn recover(&mut self, outcome: &Vote) -> usize {
+        let dissenters = match outcome {
+            Vote::Majority { dissenters, .. } if self.mode == Mode::Spatial => dissenters,
+            _ => return 0,
+        };
+
+        let healthy = (0..self.degree)
+            .filter(|r| !dissenters.contains(r))
+            .find(|&r| self.checkpoints[r].is_some());
+        let cp = match healthy.and_then(|r| self.checkpoints[r].as_ref()) {
+            Some(cp) => cp,
+            None => return 0,
+        };
+
+        let mut recovered = 0;
+        let mut restored = Vec::new();
+        for &replica in dissenters.iter().filter(|&&r| !self.is_failed(r)) {
+            if let Some(pid) = self.pid_of(replica) {
+                if process::with_process(pid, |p| p.restore(cp)).is_some() {
+                    crate::println!(
+                        "Group {}: replica {} rolled back to pc 0x{:x}",
+                        self.gid,
+                        replica,
+                        cp.get_pc()
+                    );
+                    restored.push(replica);
+                    recovered += 1;
+                }
+            }
+        }
+        // the healthy checkpoint was taken right after its signature was
+        // moved out, so restored replicas start folding from scratch too
+        for replica in restored {
+            self.flow[replica] = 0;
+        }
+        recovered
+    }
+
+    /// Marks `replica` as failed, returns its pid for the caller to
+    /// terminate once the group is no longer borrowed
+    fn fail(&mut self, replica: usize) -> Option<usize> {
+        let pid = self.pid_of(replica);
+
+        self.drop_replica(replica);
+        crate::println!("Group {}: replica {} failed", self.gid, replica);
+        pid
+    }
+
+    /// Stops waiting for `replica`, disarming the watchdog if it was the last
+    /// one left
+    fn drop_replica(&mut self, replica: usize) {
+        if let Some(failed) = self.failed.get_mut(replica) {
+            *failed = true;
+        }
+        if self.failed.iter().all(|&f| f) {
+            self.armed = false;
+        }
+    }
+
+    /// Votes on the results submitted so far, rolls back the replicas that
+    /// disagreed and prepares the group for its next vote, letting the
+    /// replicas waiting on it run again
+    fn finish_vote(&mut self) -> Vote {
+        let start = cycle::read64();
+        let flow = vote(&self.signatures);
+        let outcome = merge(vote(&self.results), &flow);
+
+        if !matches!(flow, Vote::Unanimous(_)) {
+            crate::println!("Group {}: control flow {}", self.gid, flow);
+        }
+        crate::println!("Group {}: {}", self.gid, outcome);
+        fault::report_vote(self.gid, &outcome);
+
+        let recovered = self.recover(&outcome);
+        self.reset_results();
+        self.run = 0;
+        self.arm();
+        for pid in self.live_members() {
+            process::with_process(pid, |p| p.end_vote());
+        }
+
+        stats::record_vote(self.gid, &outcome, cycle::read64().wrapping_sub(start));
+        stats::record_recoveries(self.gid, recovered);
+        outcome
+    }
+
+    /// Votes without the replicas that missed the deadline
+    ///
+    /// Silent replicas are rolled back to a healthy sibling when the group
+    /// restarts replicas and a majority exists, and failed otherwise. A
+    /// temporal replica with a run outstanding is restarted from its first
+    /// run. Returns the pids of the failed replicas.
+    fn expire(&mut self) -> Vec<usize> {
+        let mut failed = Vec::new();
+
+        let silent: Vec<usize> = match self.mode {
+            Mode::Spatial => (0..self.degree)
+                .filter(|&r| self.results[r].is_none() && !self.is_failed(r))
+                .collect(),
+            // once every run submitted, the replica is only waiting for the
+            // voter and has no run left to restart
+            Mode::Temporal => (0..1)
+                .filter(|&r| !self.is_failed(r) && !self.has_all_results())
+                .collect(),
+        };
+
+        crate::println!(
+            "Group {}: watchdog expired, replica(s) {:?} silent",
+            self.gid,
+            silent
+        );
+
+        stats::record_timeout(self.gid);
+
+        if !self.restart {
+            failed.extend(silent.iter().filter_map(|&r| self.fail(r)));
+        }
+
+        let outcome = self.finish_vote();
+
+        if self.restart && self.mode == Mode::Temporal {
+            let entry = self.entry;
+            for pid in silent.iter().filter_map(|&r| self.pid_of(r)) {
+                process::with_process(pid, |p| p.rewind(entry));
+            }
+        } else if self.restart && outcome.value().is_none() {
+            failed.extend(silent.iter().filter_map(|&r| self.fail(r)));
+        }
+        failed
+    }
+
+    /// Stores `value` as the result of `replica` and checkpoints it, returns
+    /// whether the group is ready to vote
+    ///
+    /// A temporal replica is restarted until it has run `degree` times.
+    fn store(&mut self, replica: usize, value: usize) -> bool {
+        self.submit(replica, value);
+
+        if self.mode == Mode::Spatial {
+            self.checkpoint(replica);
+        }
+
+        if self.has_all_results() {
+            true
+        } else {
+            if self.mode == Mode::Temporal {
+                self.next_run();
+            }
+            false
+        }
+    }
+
+    /// Whether every replica submitted a result that was not voted on yet
+    fn is_pending(&self) -> bool {
+        self.results.iter().any(|r| r.is_some()) && self.has_all_results()
+    }
+
+    /// Adds `pid` to the group and returns its replica id
+    fn join(&mut self, pid: usize) -> Option<usize> {
+        if self.is_complete() {
+            return None;
+        }
+        self.members.push(pid);
+        if self.is_complete() {
+            self.arm();
+        }
+        Some(self.members.len() - 1)
+    }
+}
+
+pub fn init() {
+    unsafe {
+        REPLICA_GROUPS = Some(VecDeque::with_capacity(4));
+    }
+}
+
+/// Registers a new, empty group of `degree` replicas running `entry`
+pub fn create_group(degree: usize, entry: usize, mode: Mode) -> Option<GroupHandle> {
+    if degree == 0 {
+        return None;
+    }
+
+    unsafe {
+        let groups = REPLICA_GROUPS.as_mut()?;
+        let group = ReplicaGroup::new(NEXT_GID, degree, entry, mode);
+        let handle = group.handle();
+
+        NEXT_GID += 1;
+        groups.push_back(group);
+        stats::register(&handle);
+        Some(handle)
+    }
+}
+
+/// Forgets the group `gid`, returning it
+pub fn remove_group(gid: usize) -> Option<ReplicaGroup> {
+    unsafe {
+        let groups = REPLICA_GROUPS.as_mut()?;
+        let at = groups.iter().position(|g| g.gid == gid)?;
+
+        stats::unregister(gid);
+        groups.remove(at)
+    }
+}
+
+/// Runs `f` on the group `gid`, if it exists
+pub fn with_group<R>(gid: usize, f: impl FnOnce(&mut ReplicaGroup) -> R) -> Option<R> {
+    unsafe {
+        REPLICA_GROUPS
+            .as_mut()?
+            .iter_mut()
+            .find(|g| g.gid == gid)
+            .map(f)
+    }
+}
+
+/// Adds `pid` to the group `gid` and returns its replica id
+pub fn join(gid: usize, pid: usize) -> Option<usize> {
+    with_group(gid, |g| g.join(pid)).flatten()
+}
+
+/// Stores `value` as the result of replica `replica` of group `gid`
+///
+/// The replica is checkpointed as it submits. Once every replica has
+/// submitted, the group votes, rolls back the replicas that disagreed, clears
+/// its slots and returns the outcome. A temporal replica is restarted until
+/// it has run `degree` times.
+pub fn submit(gid: usize, replica: usize, value: usize) -> Option<Vote> {
+    with_group(gid, |g| {
+        if g.store(replica, value) {
+            Some(g.finish_vote())
+        } else {
+            None
+        }
+    })
+    .flatten()
+}
+
+/// Stores `value` like `submit`, leaving the vote to `vote_pending`
+///
+/// The replica is not scheduled until the group voted, so it cannot
+/// overwrite its result before. A temporal replica only waits after its
+/// last run.
+pub fn post(gid: usize, replica: usize, value: usize) {
+    with_group(gid, |g| {
+        let ready = g.store(replica, value);
+        if g.mode == Mode::Spatial || ready {
+            if let Some(pid) = g.pid_of(replica) {
+                process::with_process(pid, |p| p.await_vote());
+            }
+        }
+    });
+}
+
+/// Votes for every group whose replicas all submitted, returns the gid and
+/// outcome of each vote
+pub fn vote_pending() -> Vec<(usize, Vote)> {
+    let mut outcomes = Vec::new();
+
+    unsafe {
+        if let Some(groups) = REPLICA_GROUPS.as_mut() {
+            for group in groups.iter_mut().filter(|g| g.is_pending()) {
+                outcomes.push((group.gid, group.finish_vote()));
+            }
+        }
+    }
+    outcomes
+}
+
+/// Stops waiting for replica `replica` of group `gid`, which exited
+pub fn leave(gid: usize, replica: usize) {
+    with_group(gid, |g| g.drop_replica(replica));
+}
+
+/// Records that replica `replica` of group `gid` reached the control-flow
+/// checkpoint `id`
+pub fn sign_checkpoint(gid: usize, replica: usize, id: usize) {
+    with_group(gid, |g| g.sign(replica, id));
+}
+
+/// Expires the watchdog of every group whose deadline passed by `now` and
+/// terminates the replicas that failed
+///
+/// Groups whose replicas have all failed or exited are disarmed and left
+/// alone.
+pub fn check_deadlines(now: u64) {
+    let mut failed = Vec::new();
+
+    unsafe {
+        if let Some(groups) = REPLICA_GROUPS.as_mut() {
+            for group in groups.iter_mut().filter(|g| g.has_expired(now)) {
+                failed.extend(group.expire());
+            }
+        }
+    }
+    for pid in failed {
+        signal::kill(pid, Signal::Terminate);
+    }
+}
+
+pub fn print_groups() {
+    unsafe {
+        if let Some(groups) = REPLICA_GROUPS.as_ref() {
+            for group in groups.iter() {
+                crate::println!(
+                    "Group {}: {:?} degree {}, entry 0x{:x}, members {:?}, results {:?}",
+                    group.gid,
+                    group.mode,
+                    group.degree,
+                    group.entry,
+                    group.members,
+                    group.results
+                );
+            }
+        }
+    }
+}
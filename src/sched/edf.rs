@@ -0,0 +1,84 @@
+use super::{Scheduler, Task};
+use alloc::collections::vec_deque::VecDeque;
+
+/// Earliest-deadline-first scheduling
+///
+/// The ready task with the earliest absolute `deadline` runs, tasks without
+/// a deadline only run when no task with one is ready. Tasks of equal
+/// deadline take turns.
+pub struct Edf {
+    /// Tasks sorted by increasing deadline
+    queue: VecDeque<Task>,
+    /// Deadlines that passed while their task was still queued
+    misses: usize,
+    /// `mtime` of the last tick
+    last_tick: u64,
+}
+
+impl Edf {
+    pub fn new() -> Self {
+        Edf {
+            queue: VecDeque::new(),
+            misses: 0,
+            last_tick: 0,
+        }
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Index right after the last task due no later than `deadline`
+    fn end_of(&self, deadline: u64) -> usize {
+        self.queue
+            .iter()
+            .position(|t| t.due() > deadline)
+            .unwrap_or_else(|| self.queue.len())
+    }
+}
+
+impl Default for Edf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Task {
+    /// Deadline used for ordering, tasks without one go last
+    fn due(&self) -> u64 {
+        self.deadline.unwrap_or(u64::MAX)
+    }
+}
+
+impl Scheduler for Edf {
+    fn enqueue(&mut self, task: Task) {
+        let at = self.end_of(task.due());
+        self.queue.insert(at, task);
+    }
+
+    fn dequeue(&mut self, pid: usize) -> Option<Task> {
+        let at = self.queue.iter().position(|t| t.pid == pid)?;
+        self.queue.remove(at)
+    }
+
+    fn pick_next(&mut self, ready: &dyn Fn(usize) -> bool) -> Option<usize> {
+        let at = self.queue.iter().position(|t| ready(t.pid))?;
+        let task = self.queue.remove(at)?;
+
+        self.enqueue(task);
+        Some(task.pid)
+    }
+
+    /// Reports every deadline that passed since the last tick
+    fn tick(&mut self, now: u64) {
+        for task in self.queue.iter() {
+            if let Some(deadline) = task.deadline {
+                if deadline > self.last_tick && deadline <= now {
+                    crate::println!("EDF: pid {} missed its deadline {}", task.pid, deadline);
+                    self.misses += 1;
+                }
+            }
+        }
+        self.last_tick = now;
+    }
+}
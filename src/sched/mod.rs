@@ -0,0 +1,273 @@
+use crate::arch;
+use crate::consts;
+use crate::process::{self, State};
+use crate::slab;
+use alloc::boxed::Box;
+use alloc::collections::vec_deque::VecDeque;
+
+pub mod edf;
+pub mod priority;
+pub mod round_robin;
+
+pub use edf::Edf;
+pub use priority::Priority;
+pub use round_robin::RoundRobin;
+
+/// What a scheduling policy knows about a process
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Task {
+    pub pid: usize,
+    /// Higher runs first under `Policy::Priority`
+    pub priority: usize,
+    /// Absolute `mtime` deadline used by `Policy::EarliestDeadline`
+    pub deadline: Option<u64>,
+}
+
+/// A scheduling policy
+///
+/// Policies only order tasks, whether a task may run is decided by the
+/// process's state and passed to `pick_next` as `ready`.
+pub trait Scheduler {
+    /// Makes the scheduler aware of `task`
+    fn enqueue(&mut self, task: Task);
+    /// Forgets the task `pid`, returning it
+    fn dequeue(&mut self, pid: usize) -> Option<Task>;
+    /// Picks the next task to run among those for which `ready` holds
+    fn pick_next(&mut self, ready: &dyn Fn(usize) -> bool) -> Option<usize>;
+    /// Called on every timer interrupt
+    fn tick(&mut self, _now: u64) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    RoundRobin,
+    Priority,
+    EarliestDeadline,
+}
+
+impl Policy {
+    pub fn build(self) -> Box<dyn Scheduler> {
+        match self {
+            Policy::RoundRobin => Box::new(RoundRobin::new()),
+            Policy::Priority => Box::new(Priority::new()),
+            Policy::EarliestDeadline => Box::new(Edf::new()),
+        }
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "sched-edf")] {
+        pub const DEFAULT_POLICY: Policy = Policy::EarliestDeadline;
+    } else if #[cfg(feature = "sched-priority")] {
+        pub const DEFAULT_POLICY: Policy = Policy::Priority;
+    } else {
+        pub const DEFAULT_POLICY: Policy = Policy::RoundRobin;
+    }
+}
+
+static mut SCHEDULER: Option<Box<dyn Scheduler>> = None;
+
+fn with_scheduler<R>(f: impl FnOnce(&mut dyn Scheduler) -> R) -> Option<R> {
+    unsafe { SCHEDULER.as_mut().map(|s| f(s.as_mut())) }
+}
+
+/// Makes the process described by `task` schedulable
+pub fn enqueue(task: Task) {
+    with_scheduler(|s| s.enqueue(task));
+}
+
+/// Stops scheduling `pid`
+pub fn dequeue(pid: usize) -> Option<Task> {
+    with_scheduler(|s| s.dequeue(pid)).flatten()
+}
+
+/// Lets the policy account for the passing time, called on every timer
+/// interrupt
+pub fn tick() {
+    let now = get_mtime();
+    with_scheduler(|s| s.tick(now));
+}
+
+// TODO: move this to RISC-V
+pub const MMIO_MTIMECMP: *mut u64 = 0x0200_4000usize as *mut u64;
+pub const MMIO_MTIME: *const u64 = 0x0200_BFF8 as *const u64;
+
+pub fn init_sched(qm: u16) {
+    unsafe {
+        MMIO_MTIMECMP.write_volatile(
+            MMIO_MTIME
+                .read_volatile()
+                .wrapping_add(consts::CONTEXT_SWITCH_TIME * qm as u64),
+        );
+    }
+}
+
+/// Arms the timer for the quantum of the task whose frame is at
+/// `frame_addr`, frames without a quantum get `consts::DEFAULT_QUANTUM`
+pub fn arm_timer(frame_addr: usize) {
+    let qm = unsafe { (*(frame_addr as *const arch::isa::trap::TrapFrame)).qm };
+
+    if qm == 0 {
+        init_sched(consts::DEFAULT_QUANTUM);
+    } else {
+        init_sched(qm as u16);
+    }
+}
+
+/// Arms the timer for the task whose frame is at `frame_addr` and switches
+/// to it, in the mode its frame runs in
+pub fn run(frame_addr: usize) -> ! {
+    arm_timer(frame_addr);
+    arch::frame::switch(frame_addr, arch::frame::mode_of(frame_addr))
+}
+
+pub fn get_mtime() -> u64 {
+    unsafe { MMIO_MTIME.read_volatile() }
+}
+
+/// Sleeping processes as `(wake up mtime, pid)`, earliest first
+static mut SLEEP_QUEUE: Option<VecDeque<(u64, usize)>> = None;
+
+/// Queues `pid` to be woken up once `mtime` reaches `until`
+pub fn add_sleeper(pid: usize, until: u64) {
+    unsafe {
+        let queue = SLEEP_QUEUE.get_or_insert_with(VecDeque::new);
+        let at = queue
+            .iter()
+            .position(|&(deadline, _)| deadline > until)
+            .unwrap_or_else(|| queue.len());
+
+        queue.insert(at, (until, pid));
+    }
+}
+
+/// Takes `pid` out of the sleep queue, so its pid can be reused
+pub fn remove_sleeper(pid: usize) {
+    if let Some(queue) = unsafe { SLEEP_QUEUE.as_mut() } {
+        queue.retain(|&(_, sleeper)| sleeper != pid);
+    }
+}
+
+/// Wakes up every sleeper whose deadline passed by `now`
+///
+/// Processes that stopped sleeping in the meantime, because they died for
+/// instance, are only removed from the queue.
+pub fn wake_sleepers(now: u64) {
+    let queue = match unsafe { SLEEP_QUEUE.as_mut() } {
+        Some(queue) => queue,
+        None => return,
+    };
+
+    while let Some(&(until, pid)) = queue.front() {
+        if until > now {
+            break;
+        }
+        queue.pop_front();
+
+        process::with_process(pid, |p| {
+            if let State::Sleeping = p.state {
+                if p.get_sleep_until() == until {
+                    p.state = State::Running;
+                }
+            }
+        });
+    }
+}
+
+/// The task run when no process is ready
+struct Idle {
+    frame: *mut arch::isa::trap::TrapFrame,
+    /// `mtime` when the idle task was last switched to, if it is running
+    since: Option<u64>,
+    /// `mtime` ticks spent idling
+    ticks: u64,
+}
+
+static mut IDLE: Option<Idle> = None;
+
+/// Waits for the next interrupt, forever
+fn idle() {
+    loop {
+        unsafe {
+            asm!("wfi");
+        }
+    }
+}
+
+/// Sets up the idle task and the scheduling `policy`
+///
+/// The idle task runs in supervisor mode, where machine timer interrupts are
+/// always taken, so the next one brings the kernel back to `schedule`.
+pub fn init(policy: Policy) {
+    let frame: *mut arch::isa::trap::TrapFrame = slab::zalloc();
+    let stack = arch::mem::zalloc(1);
+
+    arch::frame::create_kernel_task(
+        unsafe { &mut *frame },
+        idle as usize,
+        stack as usize + arch::isa::page::PAGE_SIZE,
+        0,
+    );
+
+    unsafe {
+        IDLE = Some(Idle {
+            frame,
+            since: None,
+            ticks: 0,
+        });
+        SCHEDULER = Some(policy.build());
+    }
+}
+
+/// Switches to the idle task, returning its frame address
+fn run_idle(now: u64) -> usize {
+    match unsafe { IDLE.as_mut() } {
+        Some(idle) => {
+            if idle.since.is_none() {
+                idle.since = Some(now);
+            }
+            idle.frame as usize
+        }
+        None => panic!("No process is ready and there is no idle task"),
+    }
+}
+
+/// Accounts the time the idle task ran since it was switched to
+fn stop_idle(now: u64) {
+    if let Some(idle) = unsafe { IDLE.as_mut() } {
+        if let Some(since) = idle.since.take() {
+            idle.ticks += now.wrapping_sub(since);
+        }
+    }
+}
+
+/// `mtime` ticks spent in the idle task so far
+pub fn idle_ticks() -> u64 {
+    unsafe { IDLE.as_ref().map(|idle| idle.ticks).unwrap_or(0) }
+}
+
+fn is_ready(pid: usize) -> bool {
+    process::with_process(pid, |p| {
+        matches!(p.state, State::Running) && !p.is_stopped()
+    })
+    .unwrap_or(false)
+}
+
+/// Picks the next process to run and returns its frame address
+///
+/// If no process is ready the idle task runs.
+pub fn schedule() -> usize {
+    let now = get_mtime();
+
+    stop_idle(now);
+    wake_sleepers(now);
+
+    let next = with_scheduler(|s| s.pick_next(&is_ready)).flatten();
+    let frame = next.and_then(|pid| {
+        process::with_process(pid, |p| {
+            p.usage.switches += 1;
+            p.get_frame_addr()
+        })
+    });
+    frame.unwrap_or_else(|| run_idle(now))
+}
@@ -0,0 +1,55 @@
+use super::{Scheduler, Task};
+use alloc::collections::vec_deque::VecDeque;
+
+/// Fixed-priority preemptive scheduling
+///
+/// The ready task with the highest `priority` always runs, tasks of equal
+/// priority take turns. Since `schedule` runs on every timer interrupt, a
+/// task becoming ready preempts any lower priority task within a tick.
+pub struct Priority {
+    /// Tasks sorted by decreasing priority
+    queue: VecDeque<Task>,
+}
+
+impl Priority {
+    pub fn new() -> Self {
+        Priority {
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Index right after the last task of priority at least `priority`
+    fn end_of(&self, priority: usize) -> usize {
+        self.queue
+            .iter()
+            .position(|t| t.priority < priority)
+            .unwrap_or_else(|| self.queue.len())
+    }
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler for Priority {
+    fn enqueue(&mut self, task: Task) {
+        let at = self.end_of(task.priority);
+        self.queue.insert(at, task);
+    }
+
+    fn dequeue(&mut self, pid: usize) -> Option<Task> {
+        let at = self.queue.iter().position(|t| t.pid == pid)?;
+        self.queue.remove(at)
+    }
+
+    fn pick_next(&mut self, ready: &dyn Fn(usize) -> bool) -> Option<usize> {
+        let at = self.queue.iter().position(|t| ready(t.pid))?;
+        let task = self.queue.remove(at)?;
+
+        // move behind its peers so they get their turn
+        self.enqueue(task);
+        Some(task.pid)
+    }
+}
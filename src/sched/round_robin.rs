@@ -0,0 +1,44 @@
+use super::{Scheduler, Task};
+use alloc::collections::vec_deque::VecDeque;
+
+/// Runs every ready task in turn
+pub struct RoundRobin {
+    queue: VecDeque<Task>,
+}
+
+impl RoundRobin {
+    pub fn new() -> Self {
+        RoundRobin {
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl Default for RoundRobin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler for RoundRobin {
+    fn enqueue(&mut self, task: Task) {
+        self.queue.push_back(task);
+    }
+
+    fn dequeue(&mut self, pid: usize) -> Option<Task> {
+        let at = self.queue.iter().position(|t| t.pid == pid)?;
+        self.queue.remove(at)
+    }
+
+    fn pick_next(&mut self, ready: &dyn Fn(usize) -> bool) -> Option<usize> {
+        for _ in 0..self.queue.len() {
+            let task = self.queue.pop_front()?;
+            self.queue.push_back(task);
+
+            if ready(task.pid) {
+                return Some(task.pid);
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{process, syscall};
+use core::convert::TryFrom;
+
+/// Signals a process can receive through `kill`
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+    /// Kills the process, its exit status is `process::KILLED`
+    Terminate = 1,
+    /// Stops scheduling the process until it receives `Continue`
+    Stop,
+    Continue,
+    /// Runs the process's handler, set with `syscall_sigaction`, or
+    /// terminates it if it has none
+    User,
+}
+
+impl TryFrom<usize> for Signal {
+    type Error = ();
+
+    fn try_from(v: usize) -> Result<Self, Self::Error> {
+        match v {
+            x if x == Signal::Terminate as usize => Ok(Signal::Terminate),
+            x if x == Signal::Stop as usize => Ok(Signal::Stop),
+            x if x == Signal::Continue as usize => Ok(Signal::Continue),
+            x if x == Signal::User as usize => Ok(Signal::User),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Entry point of signal handlers, runs in the target process
+///
+/// `deliver` points the target's frame here with the handler in a0 and the
+/// signal in a1. Once the handler returns, `syscall_sigreturn` resumes the
+/// code the signal interrupted.
+fn trampoline(handler: usize, signal: usize) {
+    let handler: fn(usize) = unsafe { core::mem::transmute(handler) };
+
+    handler(signal);
+    syscall::syscall_sigreturn();
+}
+
+/// Sends `signal` to `pid`
+///
+/// Returns `false` if there is no such process, it already exited or it is a
+/// kernel thread.
+pub fn kill(pid: usize, signal: Signal) -> bool {
+    let alive = process::with_process(pid, |p| !p.is_zombie() && !p.is_kernel());
+    if alive != Some(true) {
+        return false;
+    }
+
+    crate::println!("Process {} received {:?}", pid, signal);
+    match signal {
+        Signal::Terminate => process::exit(pid, process::KILLED),
+        Signal::Stop => {
+            process::with_process(pid, |p| p.stop());
+        }
+        Signal::Continue => {
+            process::with_process(pid, |p| p.resume());
+        }
+        Signal::User => {
+            let handled = process::with_process(pid, |p| p.deliver(trampoline as usize, signal));
+            if handled == Some(false) {
+                process::exit(pid, process::KILLED);
+            }
+        }
+    }
+    true
+}
+
+/// Returns from the handler `pid` is running, delivering the next pending
+/// signal if there is one
+pub fn sigreturn(pid: usize) -> bool {
+    process::with_process(pid, |p| p.leave_handler(trampoline as usize)).unwrap_or(false)
+}
@@ -0,0 +1,252 @@
+use crate::arch;
+use crate::arch::isa::page::{Table, PAGE_SIZE};
+use crate::arch::isa::trap::TrapFrame;
+use crate::process::Process;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of};
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, null_mut};
+
+/// Usage counters of a cache
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub name: &'static str,
+    /// Bytes each object takes in a slab
+    pub object_size: usize,
+    pub slabs: usize,
+    /// Objects the slabs hold, in use or not
+    pub capacity: usize,
+    pub in_use: usize,
+    /// Most objects in use at once
+    pub peak: usize,
+    pub allocs: usize,
+    pub frees: usize,
+}
+
+/// One `key=value` line per cache, prefixed with `SLAB`
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "SLAB name={} object_size={} slabs={} capacity={} in_use={} peak={} allocs={} frees={}",
+            self.name,
+            self.object_size,
+            self.slabs,
+            self.capacity,
+            self.in_use,
+            self.peak,
+            self.allocs,
+            self.frees
+        )
+    }
+}
+
+/// A free object, linked through its first word
+struct Free {
+    next: *mut Free,
+}
+
+/// Hands out objects of type `T` packed in slabs of whole pages
+///
+/// A cache grows by a slab whenever it runs out of free objects. Slabs are
+/// never returned to the page allocator, freed objects are kept for the next
+/// allocation of the same type.
+pub struct Cache<T> {
+    free: *mut Free,
+    stats: CacheStats,
+    marker: PhantomData<T>,
+}
+
+impl<T> Cache<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Cache {
+            free: null_mut(),
+            stats: CacheStats {
+                name,
+                object_size: 0,
+                slabs: 0,
+                capacity: 0,
+                in_use: 0,
+                peak: 0,
+                allocs: 0,
+                frees: 0,
+            },
+            marker: PhantomData,
+        }
+    }
+
+    /// Bytes each object takes, large enough to link it when free and
+    /// keeping every object of a slab aligned
+    pub fn object_size() -> usize {
+        let align = align_of::<T>().max(align_of::<Free>());
+        let size = size_of::<T>().max(size_of::<Free>());
+
+        (size + align - 1) & !(align - 1)
+    }
+
+    /// Pages of a slab, enough for at least one object
+    fn slab_pages() -> usize {
+        (Self::object_size() + PAGE_SIZE - 1) / PAGE_SIZE
+    }
+
+    /// Adds a slab of free objects, returns `false` when out of pages
+    fn grow(&mut self) -> bool {
+        let pages = Self::slab_pages();
+        let slab = arch::mem::alloc(pages);
+        if slab.is_null() {
+            return false;
+        }
+
+        let size = Self::object_size();
+        let objects = pages * PAGE_SIZE / size;
+        // link them backwards so the slab is handed out in address order
+        for i in (0..objects).rev() {
+            let free = unsafe { slab.add(i * size) } as *mut Free;
+            unsafe { (*free).next = self.free };
+            self.free = free;
+        }
+
+        self.stats.object_size = size;
+        self.stats.slabs += 1;
+        self.stats.capacity += objects;
+        true
+    }
+
+    /// Returns an uninitialized object, null when out of memory
+    pub fn alloc(&mut self) -> *mut T {
+        if self.free.is_null() && !self.grow() {
+            return null_mut();
+        }
+
+        let object = self.free;
+        self.free = unsafe { (*object).next };
+
+        self.stats.in_use += 1;
+        self.stats.allocs += 1;
+        self.stats.peak = self.stats.peak.max(self.stats.in_use);
+        object as *mut T
+    }
+
+    /// Returns a zeroed object, null when out of memory
+    pub fn zalloc(&mut self) -> *mut T {
+        let object = self.alloc();
+        if !object.is_null() {
+            unsafe { ptr::write_bytes(object as *mut u8, 0, Self::object_size()) };
+        }
+        object
+    }
+
+    /// Gives `object` back to the cache, it must come from this cache and
+    /// no longer be used
+    pub fn free(&mut self, object: *mut T) {
+        if object.is_null() {
+            return;
+        }
+
+        let free = object as *mut Free;
+        unsafe { (*free).next = self.free };
+        self.free = free;
+
+        self.stats.in_use -= 1;
+        self.stats.frees += 1;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+}
+
+/// Types with a cache of their own
+pub trait Cached: Sized + 'static {
+    fn cache() -> &'static mut Cache<Self>;
+}
+
+static mut FRAMES: Cache<TrapFrame> = Cache::new("TrapFrame");
+static mut TABLES: Cache<Table> = Cache::new("Table");
+static mut PROCESSES: Cache<Process> = Cache::new("Process");
+
+impl Cached for TrapFrame {
+    fn cache() -> &'static mut Cache<Self> {
+        unsafe { &mut FRAMES }
+    }
+}
+
+impl Cached for Table {
+    fn cache() -> &'static mut Cache<Self> {
+        unsafe { &mut TABLES }
+    }
+}
+
+impl Cached for Process {
+    fn cache() -> &'static mut Cache<Self> {
+        unsafe { &mut PROCESSES }
+    }
+}
+
+/// Allocates a zeroed `T` from its cache, null when out of memory
+pub fn zalloc<T: Cached>() -> *mut T {
+    T::cache().zalloc()
+}
+
+/// Gives `object`, allocated with `zalloc`, back to its cache
+pub fn free<T: Cached>(object: *mut T) {
+    T::cache().free(object)
+}
+
+/// Counters of every cache
+pub fn stats() -> [CacheStats; 3] {
+    [
+        TrapFrame::cache().stats(),
+        Table::cache().stats(),
+        Process::cache().stats(),
+    ]
+}
+
+/// A `T` stored in its cache, dropped and given back with the handle
+pub struct Owned<T: Cached> {
+    object: *mut T,
+}
+
+impl<T: Cached> Owned<T> {
+    /// Moves `value` into its cache, returns `None` when out of memory
+    pub fn new(value: T) -> Option<Self> {
+        let object = T::cache().alloc();
+        if object.is_null() {
+            return None;
+        }
+
+        unsafe { object.write(value) };
+        Some(Owned { object })
+    }
+
+    /// Moves the value out of the cache
+    pub fn into_inner(self) -> T {
+        let value = unsafe { self.object.read() };
+
+        T::cache().free(self.object);
+        core::mem::forget(self);
+        value
+    }
+}
+
+impl<T: Cached> Deref for Owned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.object }
+    }
+}
+
+impl<T: Cached> DerefMut for Owned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.object }
+    }
+}
+
+impl<T: Cached> Drop for Owned<T> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.object) };
+        T::cache().free(self.object);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::replica::{GroupHandle, Mode, Vote};
+use crate::{process, sched, slab};
+use alloc::vec::Vec;
+use core::fmt;
+use riscv::register::{cycle, instret};
+
+/// Fault-tolerance counters of a replica group
+#[derive(Debug, Clone, Copy)]
+pub struct GroupStats {
+    pub gid: usize,
+    pub mode: Mode,
+    pub degree: usize,
+    pub votes: usize,
+    /// Votes where at least one replica dissented, or no majority was found
+    pub disagreements: usize,
+    pub no_majority: usize,
+    /// Replicas rolled back to a healthy sibling
+    pub recoveries: usize,
+    /// Votes forced by the watchdog
+    pub timeouts: usize,
+    /// Cycles spent running the group's replicas
+    pub replica_cycles: u64,
+    /// Cycles spent voting and recovering
+    pub vote_cycles: u64,
+}
+
+impl GroupStats {
+    fn new(handle: &GroupHandle) -> Self {
+        GroupStats {
+            gid: handle.gid,
+            mode: handle.mode,
+            degree: handle.degree,
+            votes: 0,
+            disagreements: 0,
+            no_majority: 0,
+            recoveries: 0,
+            timeouts: 0,
+            replica_cycles: 0,
+            vote_cycles: 0,
+        }
+    }
+}
+
+/// One `key=value` line per group, prefixed with `STATS`
+impl fmt::Display for GroupStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self.mode {
+            Mode::Spatial => "spatial",
+            Mode::Temporal => "temporal",
+        };
+
+        write!(
+            f,
+            "STATS gid={} mode={} degree={} votes={} disagreements={} no_majority={} \
+             recoveries={} timeouts={} replica_cycles={} vote_cycles={}",
+            self.gid,
+            mode,
+            self.degree,
+            self.votes,
+            self.disagreements,
+            self.no_majority,
+            self.recoveries,
+            self.timeouts,
+            self.replica_cycles,
+            self.vote_cycles
+        )
+    }
+}
+
+struct Stats {
+    groups: Vec<GroupStats>,
+    /// Usage of the processes that were reaped, which the process table no
+    /// longer holds
+    exited: Vec<process::UsageRecord>,
+    /// `mtime` when the kernel started
+    boot: u64,
+    /// `mcycle` when the kernel last left to a process
+    resumed: u64,
+    /// `minstret` when the kernel last left to a process
+    resumed_instret: u64,
+    reported: bool,
+}
+
+static mut STATS: Option<Stats> = None;
+
+pub fn init() {
+    unsafe {
+        STATS = Some(Stats {
+            groups: Vec::new(),
+            exited: Vec::new(),
+            boot: sched::get_mtime(),
+            resumed: cycle::read64(),
+            resumed_instret: instret::read64(),
+            reported: false,
+        });
+    }
+}
+
+fn with_stats<R>(f: impl FnOnce(&mut Stats) -> R) -> Option<R> {
+    unsafe { STATS.as_mut().map(f) }
+}
+
+fn with_group_stats(gid: usize, f: impl FnOnce(&mut GroupStats)) {
+    with_stats(|stats| {
+        if let Some(group) = stats.groups.iter_mut().find(|g| g.gid == gid) {
+            f(group);
+        }
+    });
+}
+
+/// Starts counting for a new group
+pub fn register(handle: &GroupHandle) {
+    with_stats(|stats| stats.groups.push(GroupStats::new(handle)));
+}
+
+/// Stops counting for the group `gid`
+pub fn unregister(gid: usize) {
+    with_stats(|stats| stats.groups.retain(|g| g.gid != gid));
+}
+
+/// Returns the counters of group `gid`
+pub fn group(gid: usize) -> Option<GroupStats> {
+    with_stats(|stats| stats.groups.iter().find(|g| g.gid == gid).copied()).flatten()
+}
+
+/// Counts a vote of `gid` that took `cycles`
+pub fn record_vote(gid: usize, outcome: &Vote, cycles: u64) {
+    with_group_stats(gid, |group| {
+        group.votes += 1;
+        group.vote_cycles += cycles;
+        match outcome {
+            Vote::Unanimous(_) => {}
+            Vote::Majority { .. } => group.disagreements += 1,
+            Vote::NoMajority => {
+                group.disagreements += 1;
+                group.no_majority += 1;
+            }
+        }
+    });
+}
+
+pub fn record_recoveries(gid: usize, recovered: usize) {
+    with_group_stats(gid, |group| group.recoveries += recovered);
+}
+
+/// Keeps the usage of a process that is being reaped
+pub fn record_exit(record: process::UsageRecord) {
+    with_stats(|stats| stats.exited.push(record));
+}
+
+/// Usage of every process reaped so far
+pub fn exited() -> Vec<process::UsageRecord> {
+    with_stats(|stats| stats.exited.clone()).unwrap_or_default()
+}
+
+pub fn record_timeout(gid: usize) {
+    with_group_stats(gid, |group| group.timeouts += 1);
+}
+
+/// Marks the kernel leaving to a process, called right before switching
+pub fn resume() {
+    with_stats(|stats| {
+        stats.resumed = cycle::read64();
+        stats.resumed_instret = instret::read64();
+    });
+}
+
+/// Credits the cycles and instructions since the last `resume` to `pid` and
+/// its group, called when a trap enters the kernel
+pub fn trap_entry(pid: usize) {
+    let elapsed = with_stats(|stats| {
+        (
+            cycle::read64().wrapping_sub(stats.resumed),
+            instret::read64().wrapping_sub(stats.resumed_instret),
+        )
+    });
+    let (cycles, instructions) = match elapsed {
+        Some(elapsed) => elapsed,
+        None => return,
+    };
+
+    process::with_process(pid, |p| {
+        p.usage.cycles += cycles;
+        p.usage.instructions += instructions;
+    });
+    if let Some((gid, _)) = process::group_of(pid) {
+        with_group_stats(gid, |group| group.replica_cycles += cycles);
+    }
+}
+
+/// Milliseconds since `init`, measured with `mtime` at `consts::FREQ`
+pub fn uptime_ms() -> u64 {
+    with_stats(|stats| sched::get_mtime().wrapping_sub(stats.boot) * 1000 / crate::consts::FREQ)
+        .unwrap_or(0)
+}
+
+/// Milliseconds spent in the idle task
+pub fn idle_ms() -> u64 {
+    sched::idle_ticks() * 1000 / crate::consts::FREQ
+}
+
+/// Prints every group's counters, once
+///
+/// The report starts with `STATS-BEGIN` and ends with `STATS-END`, every
+/// line in between holds a group's counters, a process's usage or a slab
+/// cache's usage, as `key=value` pairs.
+pub fn report() {
+    let uptime = uptime_ms();
+    let idle = idle_ms();
+    let stats = match unsafe { STATS.as_mut() } {
+        Some(stats) if !stats.reported => stats,
+        _ => return,
+    };
+    stats.reported = true;
+
+    crate::println!("STATS-BEGIN uptime_ms={} idle_ms={}", uptime, idle);
+    for group in stats.groups.iter() {
+        crate::println!("{}", group);
+    }
+    process::print_usage();
+    for record in stats.exited.iter() {
+        crate::println!("{}", record);
+    }
+    for cache in slab::stats().iter() {
+        crate::println!("{}", cache);
+    }
+    crate::println!("STATS-END groups={}", stats.groups.len());
+}
@@ -1,7 +1,6 @@
-use crate::{cpu, process};
+use crate::signal::{self, Signal};
+use crate::{cpu, fault, process, replica};
 use core::convert::{TryFrom, TryInto};
-use alloc::collections::vec_deque::VecDeque;
-use crate::process::{State, TMR_VALUES_LIST};
 
 pub static mut sum:usize = 0;
 
@@ -15,9 +14,24 @@ pub enum Syscall {
     Verify,
     PrintTotal,
     Print,
-    Sum
+    Sum,
+    Signature,
+    SetQuantum,
+    Wait,
+    Spawn,
+    ChildAt,
+    Kill,
+    SigAction,
+    SigReturn,
+    GetUsage,
+    DumpUsage,
+    SetPriority,
+    SetDeadline,
 }
 
+/// Number of syscalls, see `process::Usage`
+pub const SYSCALL_COUNT: usize = Syscall::SetDeadline as usize + 1;
+
 impl TryFrom<usize> for Syscall {
     type Error = ();
 
@@ -32,6 +46,18 @@ impl TryFrom<usize> for Syscall {
             x if x == Syscall::PrintTotal as usize => Ok(Syscall::PrintTotal),
             x if x == Syscall::Print as usize => Ok(Syscall::Print),
             x if x == Syscall::Sum as usize => Ok(Syscall::Sum),
+            x if x == Syscall::Signature as usize => Ok(Syscall::Signature),
+            x if x == Syscall::SetQuantum as usize => Ok(Syscall::SetQuantum),
+            x if x == Syscall::Wait as usize => Ok(Syscall::Wait),
+            x if x == Syscall::Spawn as usize => Ok(Syscall::Spawn),
+            x if x == Syscall::ChildAt as usize => Ok(Syscall::ChildAt),
+            x if x == Syscall::Kill as usize => Ok(Syscall::Kill),
+            x if x == Syscall::SigAction as usize => Ok(Syscall::SigAction),
+            x if x == Syscall::SigReturn as usize => Ok(Syscall::SigReturn),
+            x if x == Syscall::GetUsage as usize => Ok(Syscall::GetUsage),
+            x if x == Syscall::DumpUsage as usize => Ok(Syscall::DumpUsage),
+            x if x == Syscall::SetPriority as usize => Ok(Syscall::SetPriority),
+            x if x == Syscall::SetDeadline as usize => Ok(Syscall::SetDeadline),
             _ => Err(()),
         }
     }
@@ -59,6 +85,7 @@ pub unsafe fn make_syscall(pc: usize, frame_ptr: *mut crate::arch::isa::trap::Tr
  
     // skip ecall
     frame.pc = pc + 4;
+    process::with_process(frame.pid, |p| p.usage.count_syscall(syscall_id));
     match syscall_id.try_into() {
         Ok(Syscall::Nop) => {
             crate::println!("NOP");
@@ -68,17 +95,14 @@ pub unsafe fn make_syscall(pc: usize, frame_ptr: *mut crate::arch::isa::trap::Tr
             cpu::dump_registers(frame);
         }
         Ok(Syscall::Sleep) => {
-            crate::println!("Sleeping");
-            let proc = process::sleep_pid((*frame).pid, 1);
-            crate::println!("Process is {}", proc);
+            process::sleep_pid(frame.pid, frame.arg(0));
         }
         Ok(Syscall::Exit) => {
-            crate::println!("Exiting. Bye.");
-            crate::abort()
+            process::exit(frame.pid, frame.arg(0));
         }
         Ok(Syscall::PrintTotal) => {
             crate::println!("Total: {}", sum);
-            crate::println!("Tempo: {}", process::time_total);
+            print_usage(frame.pid);
         }
         
         Ok(Syscall::Sum) => {
@@ -87,54 +111,115 @@ pub unsafe fn make_syscall(pc: usize, frame_ptr: *mut crate::arch::isa::trap::Tr
 
            sum = x + y;
 
-           if (process::TMR_BOOL){
-            syscall_push_tmr(sum);
+           if process::group_of((*frame).pid).is_some() {
+            submit_result(frame.pid, sum);
            } else {
-            syscall_print_total(sum);
-           }    
+            crate::println!("Total: {}", sum);
+           }
         }
         Ok(Syscall::Print) => {
-            crate::println!("Tempo: {}", process::time_total);
+            print_usage(frame.pid);
         }
         Ok(Syscall::TmrAdd) => {
-            if let Some(mut tmr) = TMR_VALUES_LIST.take(){
-                crate::println!("Sum: {}", sum);
-                tmr.push_back(sum);
-                TMR_VALUES_LIST.replace(tmr);
-                if let Some(new_tmr) = TMR_VALUES_LIST.as_ref() {
-                    crate::println!("TMR_VALUES_LIST size: {} \n", new_tmr.len());
-                    if(new_tmr.len() >= 3){
-                        syscall_verify();
-                    }
-                }
-            }
+            submit_result(frame.pid, frame.arg(0));
         }
         Ok(Syscall::Verify) => {
-            if let Some(tmr) = TMR_VALUES_LIST.as_ref() {
-                let mut max_count = 0;
-                let mut most_common_value = 0;
-            
-                for i in 0..tmr.len() {
-                    let mut count = 0;
-                    for j in i+1..tmr.len() {
-                        if tmr[i] == tmr[j] {
-                            count += 1;
-                        }
-                    }
-                    if count > max_count {
-                        max_count = count;
-                        most_common_value = tmr[i];
-                    }
+            if let Some((gid, _)) = process::group_of(frame.pid) {
+                if let Some(outcome) = replica::with_group(gid, |g| g.vote()) {
+                    crate::println!("Group {}: {}", gid, outcome);
                 }
-            
-                crate::println!("Correct output: {}", most_common_value);
-                syscall_print();
             }
         }
+        Ok(Syscall::Signature) => {
+            if let Some((gid, id)) = process::group_of(frame.pid) {
+                replica::sign_checkpoint(gid, id, frame.arg(0));
+            }
+        }
+        Ok(Syscall::SetQuantum) => {
+            let qm = frame.arg(0).min(u16::MAX as usize) as u16;
+            process::set_quantum(frame.pid, qm);
+        }
+        Ok(Syscall::SetPriority) => {
+            let set = process::set_priority(frame.pid, frame.arg(0));
+            frame.set_return(if set { 0 } else { usize::MAX });
+        }
+        Ok(Syscall::SetDeadline) => {
+            let set = process::set_deadline_in(frame.pid, frame.arg(0));
+            frame.set_return(if set { 0 } else { usize::MAX });
+        }
+        Ok(Syscall::Wait) => match process::wait(frame.pid, frame.arg(0)) {
+            process::Wait::Exited(status) => frame.set_return(status),
+            process::Wait::Blocked => {}
+            process::Wait::NoSuchProcess => frame.set_return(usize::MAX),
+        },
+        Ok(Syscall::Spawn) => {
+            let child = process::spawn(frame.pid, frame.arg(0), frame.arg(1), frame.arg(2));
+            frame.set_return(child.unwrap_or(usize::MAX));
+        }
+        Ok(Syscall::ChildAt) => {
+            let children = process::children(frame.pid);
+            frame.set_return(children.get(frame.arg(0)).copied().unwrap_or(usize::MAX));
+        }
+        Ok(Syscall::Kill) => {
+            // a signal sent to the caller itself may run its handler or free
+            // its frame, so the return value is written first and the frame
+            // is only touched again if nothing was sent
+            frame.set_return(0);
+            let sent = match Signal::try_from(frame.arg(1)) {
+                Ok(sig) => signal::kill(frame.arg(0), sig),
+                Err(_) => false,
+            };
+            if !sent {
+                frame.set_return(usize::MAX);
+            }
+        }
+        Ok(Syscall::SigAction) => {
+            let handler = frame.arg(0);
+            let set = (handler == 0 || process::in_text(handler))
+                && process::with_process(frame.pid, |p| p.set_handler(handler)).is_some();
+            frame.set_return(if set { 0 } else { usize::MAX });
+        }
+        Ok(Syscall::SigReturn) => {
+            signal::sigreturn(frame.pid);
+        }
+        Ok(Syscall::GetUsage) => {
+            let pid = match frame.arg(0) {
+                0 => frame.pid,
+                pid => pid,
+            };
+            let value = process::with_process(pid, |p| p.usage.field(frame.arg(1))).flatten();
+            frame.set_return(value.map_or(usize::MAX, |v| v as usize));
+        }
+        Ok(Syscall::DumpUsage) => {
+            process::print_usage();
+        }
         Err(_) => panic!("Unknown syscall {}", syscall_id),
     }
 }
 
+/// Stores `value` as the result of replica `pid`, the voter kernel thread
+/// votes once its group has every result
+fn submit_result(pid: usize, value: usize) {
+    let (gid, id) = match process::group_of(pid) {
+        Some(member) => member,
+        None => {
+            crate::println!("Process {} is not a replica", pid);
+            return;
+        }
+    };
+
+    let value = fault::corrupt_result(gid, id, value);
+    crate::println!("Group {}: replica {} submitted {}", gid, id, value);
+
+    replica::post(gid, id, value);
+}
+
+fn print_usage(pid: usize) {
+    process::with_process(pid, |p| {
+        crate::println!("Process {}: {}", pid, p.usage);
+    });
+}
+
 pub fn syscall_nop() -> usize {
     unsafe { _make_syscall(Syscall::Nop as usize, 0, 0, 0, 0, 0, 0) }
 }
@@ -143,12 +228,14 @@ pub fn syscall_dump() -> usize {
     unsafe { _make_syscall(Syscall::DumpRegisters as usize, 0, 0, 0, 0, 0, 0) }
 }
 
-pub fn syscall_sleep() -> usize {
-    unsafe { _make_syscall(Syscall::Sleep as usize, 0, 0, 0, 0, 0, 0) }
+/// Sleeps for at least `ms` milliseconds
+pub fn syscall_sleep(ms: usize) -> usize {
+    unsafe { _make_syscall(Syscall::Sleep as usize, ms, 0, 0, 0, 0, 0) }
 }
 
-pub fn syscall_exit() -> usize {
-    unsafe { _make_syscall(Syscall::Exit as usize, 0, 0, 0, 0, 0, 0) }
+/// Terminates the caller with `status`, collected by `syscall_wait`
+pub fn syscall_exit(status: usize) -> usize {
+    unsafe { _make_syscall(Syscall::Exit as usize, status, 0, 0, 0, 0, 0) }
 }
 
 pub fn syscall_push_tmr(total: usize) -> usize {
@@ -167,3 +254,90 @@ pub fn syscall_print() -> usize {
 pub fn syscall_verify() -> usize {
     unsafe { _make_syscall(Syscall::Verify as usize, 0, 0, 0, 0, 0, 0) }
 }
+
+/// Marks control-flow checkpoint `id` as reached
+///
+/// Replicas reaching different checkpoints, or the same ones in a different
+/// order, are flagged by the voter even if their results agree. Outside a
+/// replica group this does nothing.
+pub fn syscall_signature(id: usize) -> usize {
+    unsafe { _make_syscall(Syscall::Signature as usize, id, 0, 0, 0, 0, 0) }
+}
+
+/// Sets the caller's time slice to `qm` times `consts::CONTEXT_SWITCH_TIME`
+pub fn syscall_set_quantum(qm: usize) -> usize {
+    unsafe { _make_syscall(Syscall::SetQuantum as usize, qm, 0, 0, 0, 0, 0) }
+}
+
+/// Sets the caller's priority, higher runs first under `sched::Priority`
+///
+/// Returns `0`, or `usize::MAX` on failure.
+pub fn syscall_set_priority(priority: usize) -> usize {
+    unsafe { _make_syscall(Syscall::SetPriority as usize, priority, 0, 0, 0, 0, 0) }
+}
+
+/// Gives the caller a deadline `ms` milliseconds from now for
+/// `sched::Edf`, or clears it if `ms` is zero
+///
+/// Returns `0`, or `usize::MAX` on failure and for replicas, whose deadline
+/// is their group's watchdog.
+pub fn syscall_set_deadline(ms: usize) -> usize {
+    unsafe { _make_syscall(Syscall::SetDeadline as usize, ms, 0, 0, 0, 0, 0) }
+}
+
+/// Waits for `pid` to exit and returns its status, or `usize::MAX` if there
+/// is no such process
+pub fn syscall_wait(pid: usize) -> usize {
+    unsafe { _make_syscall(Syscall::Wait as usize, pid, 0, 0, 0, 0, 0) }
+}
+
+/// Starts `entry` as a child of the caller with `stack_pages` of stack and
+/// returns its pid, or `usize::MAX` on failure
+///
+/// `flags` takes the replication degree and `process::SPAWN_TEMPORAL`, see
+/// `process::spawn`.
+pub fn syscall_spawn(entry: fn(), stack_pages: usize, flags: usize) -> usize {
+    unsafe {
+        _make_syscall(
+            Syscall::Spawn as usize,
+            entry as usize,
+            stack_pages,
+            flags,
+            0,
+            0,
+            0,
+        )
+    }
+}
+
+/// Returns the pid of the caller's `index`th child, or `usize::MAX` past the
+/// last one
+pub fn syscall_child_at(index: usize) -> usize {
+    unsafe { _make_syscall(Syscall::ChildAt as usize, index, 0, 0, 0, 0, 0) }
+}
+
+/// Sends `signal` to `pid`, returns `usize::MAX` if it could not be sent
+pub fn syscall_kill(pid: usize, signal: Signal) -> usize {
+    unsafe { _make_syscall(Syscall::Kill as usize, pid, signal as usize, 0, 0, 0, 0) }
+}
+
+/// Runs `handler` on `Signal::User` instead of terminating the caller
+pub fn syscall_sigaction(handler: fn(usize)) -> usize {
+    unsafe { _make_syscall(Syscall::SigAction as usize, handler as usize, 0, 0, 0, 0, 0) }
+}
+
+/// Returns from a signal handler, called by the signal trampoline
+pub fn syscall_sigreturn() -> usize {
+    unsafe { _make_syscall(Syscall::SigReturn as usize, 0, 0, 0, 0, 0, 0) }
+}
+
+/// Returns the `process::USAGE_*` field `field` of `pid`, or of the caller if
+/// `pid` is `0`, or `usize::MAX` if there is no such process or field
+pub fn syscall_getrusage(pid: usize, field: usize) -> usize {
+    unsafe { _make_syscall(Syscall::GetUsage as usize, pid, field, 0, 0, 0, 0) }
+}
+
+/// Prints the resources used by every process
+pub fn syscall_dump_usage() -> usize {
+    unsafe { _make_syscall(Syscall::DumpUsage as usize, 0, 0, 0, 0, 0, 0) }
+}
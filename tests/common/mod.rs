@@ -0,0 +1,27 @@
+//! Setup shared by the tests that run processes and replica groups
+//!
+//! Each test binary includes this with `mod common;` and uses only part of
+//! it.
+#![allow(dead_code)]
+
+use alloc::vec::Vec;
+use strail::process::{self, ProcessTable};
+use strail::replica::{self, Mode};
+
+/// Sets up memory, an empty process table and the replica groups
+pub fn init() {
+    strail::arch::mem::init();
+    strail::arch::kmem::init();
+    unsafe { process::PROCESS_TABLE = Some(ProcessTable::new()) };
+    replica::init();
+}
+
+pub fn task() {}
+
+/// Starts a group of three replicas, returns its gid and their pids
+pub fn start_group() -> (usize, Vec<usize>) {
+    let handle = process::create_replicated(task, 3, Mode::Spatial).unwrap();
+    let members = replica::with_group(handle.gid, |g| g.members().to_vec()).unwrap();
+
+    (handle.gid, members)
+}
@@ -0,0 +1,112 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(strail::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use strail::bootargs;
+use strail::fault;
+
+#[cfg(test)]
+#[no_mangle]
+extern "C" fn main() {
+    strail::arch::mem::init();
+    strail::arch::kmem::init();
+    test_main();
+    strail::exit_qemu_as_success();
+}
+
+fn push_word(blob: &mut Vec<u8>, word: u32) {
+    blob.extend_from_slice(&word.to_be_bytes());
+}
+
+/// Pushes `bytes` and a NUL, padded to a word
+fn push_str(blob: &mut Vec<u8>, bytes: &[u8]) {
+    blob.extend_from_slice(bytes);
+    blob.push(0);
+    while blob.len() % 4 != 0 {
+        blob.push(0);
+    }
+}
+
+/// A device tree with a `/memory` node holding a decoy `bootargs` and a
+/// `/chosen` node holding `args`, in words so that it is aligned
+fn device_tree(args: &str) -> Vec<u32> {
+    let strings = b"bootargs\0";
+    let mut structs = Vec::new();
+
+    push_word(&mut structs, 1);
+    push_str(&mut structs, b"");
+    push_word(&mut structs, 1);
+    push_str(&mut structs, b"memory@80000000");
+    push_word(&mut structs, 3);
+    push_word(&mut structs, 6);
+    push_word(&mut structs, 0);
+    push_str(&mut structs, b"decoy");
+    push_word(&mut structs, 2);
+    push_word(&mut structs, 4);
+    push_word(&mut structs, 1);
+    push_str(&mut structs, b"chosen");
+    push_word(&mut structs, 3);
+    push_word(&mut structs, args.len() as u32 + 1);
+    push_word(&mut structs, 0);
+    push_str(&mut structs, args.as_bytes());
+    push_word(&mut structs, 2);
+    push_word(&mut structs, 2);
+    push_word(&mut structs, 9);
+
+    let header = 40;
+    let mut blob = Vec::new();
+    for &word in &[
+        0xd00d_feed,
+        (header + structs.len() + strings.len()) as u32,
+        header as u32,
+        (header + structs.len()) as u32,
+        header as u32,
+        17,
+        16,
+        0,
+        strings.len() as u32,
+        structs.len() as u32,
+    ] {
+        push_word(&mut blob, word);
+    }
+    blob.extend_from_slice(&structs);
+    blob.extend_from_slice(strings);
+
+    blob.chunks(4)
+        .map(|chunk| {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u32::from_ne_bytes(word)
+        })
+        .collect()
+}
+
+#[test_case]
+fn test_bootargs_come_from_chosen() {
+    let dtb = device_tree("quiet strail.campaign=fault=1:0:x10:3:0;random=9:1:5:10");
+    unsafe { bootargs::init(dtb.as_ptr() as usize) };
+
+    assert_eq!(bootargs::value("quiet"), Some(""));
+    assert_eq!(bootargs::value("strail"), None);
+    assert_eq!(bootargs::value("missing"), None);
+
+    let campaign = fault::boot_campaign();
+    assert_eq!(campaign.faults.len(), 1);
+    assert_eq!(campaign.random.unwrap().seed, 9);
+}
+
+#[test_case]
+fn test_no_device_tree_means_no_bootargs() {
+    let garbage: [u32; 16] = [0; 16];
+
+    unsafe { bootargs::init(0) };
+    assert_eq!(bootargs::get(), "");
+    unsafe { bootargs::init(garbage.as_ptr() as usize) };
+    assert_eq!(bootargs::get(), "");
+    assert!(fault::boot_campaign().faults.is_empty());
+}
@@ -0,0 +1,99 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(strail::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+mod common;
+
+use alloc::vec;
+use common::start_group;
+use strail::arch::isa::trap::TrapFrame;
+use strail::process::{self, Checkpoint};
+use strail::replica::{self, Vote};
+
+#[cfg(test)]
+#[no_mangle]
+extern "C" fn main() {
+    common::init();
+    test_main();
+    strail::exit_qemu_as_success();
+}
+
+fn frame(pid: usize) -> *mut TrapFrame {
+    process::with_process(pid, |p| p.get_frame_addr()).unwrap() as *mut TrapFrame
+}
+
+fn stack(pid: usize) -> *mut u8 {
+    process::with_process(pid, |p| p.get_stack_addr()).unwrap() as *mut u8
+}
+
+#[test_case]
+fn test_restore_copies_sibling_state() {
+    let (_, pids) = start_group();
+    let mut cp = Checkpoint::new(strail::consts::STACK_PAGES).unwrap();
+
+    unsafe {
+        (*frame(pids[0])).pc = 0x1234;
+        (*frame(pids[0])).regs[10] = 7;
+        *stack(pids[0]).add(8) = 0xaa;
+    }
+    process::with_process(pids[0], |p| p.save(&mut cp)).unwrap();
+    assert_eq!(cp.get_pc(), 0x1234);
+
+    // later changes to the source do not reach the checkpoint
+    unsafe { *stack(pids[0]).add(8) = 0 };
+    process::with_process(pids[1], |p| p.restore(&cp)).unwrap();
+
+    unsafe {
+        assert_eq!((*frame(pids[1])).pc, 0x1234);
+        assert_eq!((*frame(pids[1])).regs[10], 7);
+        assert_eq!(*stack(pids[1]).add(8), 0xaa);
+        assert_eq!((*frame(pids[1])).pid, pids[1]);
+    }
+}
+
+#[test_case]
+fn test_recover_rolls_back_dissenters() {
+    let (gid, pids) = start_group();
+
+    unsafe {
+        (*frame(pids[0])).pc = 0x2000;
+        (*frame(pids[1])).pc = 0x3000;
+        (*frame(pids[2])).pc = 0x2000;
+    }
+    let recovered = replica::with_group(gid, |g| {
+        (0..3).for_each(|r| g.checkpoint(r));
+        g.recover(&Vote::Majority {
+            value: 4,
+            dissenters: vec![1],
+        })
+    });
+
+    assert_eq!(recovered, Some(1));
+    unsafe { assert_eq!((*frame(pids[1])).pc, 0x2000) };
+}
+
+#[test_case]
+fn test_recover_needs_a_majority() {
+    let (gid, pids) = start_group();
+
+    unsafe { (*frame(pids[1])).pc = 0x3000 };
+    let recovered = replica::with_group(gid, |g| {
+        (0..3).for_each(|r| g.checkpoint(r));
+        g.recover(&Vote::NoMajority)
+    });
+
+    assert_eq!(recovered, Some(0));
+    unsafe { assert_eq!((*frame(pids[1])).pc, 0x3000) };
+}
+
+#[test_case]
+fn test_checkpoint_without_memory_frees_what_it_took() {
+    let pages = strail::arch::mem::page_count();
+
+    assert!(Checkpoint::new(1 << 20).is_none());
+    assert_eq!(strail::arch::mem::page_count(), pages);
+}
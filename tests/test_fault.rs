@@ -0,0 +1,178 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(strail::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+mod common;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use common::start_group;
+use strail::arch::isa::trap::TrapFrame;
+use strail::fault::{self, Campaign, CampaignError, Fault, Prng, Target, Verdict};
+use strail::process;
+use strail::replica::{self, Mode, Vote};
+
+#[cfg(test)]
+#[no_mangle]
+extern "C" fn main() {
+    common::init();
+    test_main();
+    strail::exit_qemu_as_success();
+}
+
+fn fault(gid: usize, replica: usize, target: Target, bit: usize) -> Fault {
+    Fault {
+        gid,
+        replica,
+        target,
+        bit,
+        cycle: 0,
+    }
+}
+
+#[test_case]
+fn test_prng_is_reproducible() {
+    let mut first = Prng::new(42);
+    let mut second = Prng::new(42);
+
+    for _ in 0..16 {
+        assert_eq!(first.next_u64(), second.next_u64());
+    }
+
+    // a zero seed must not get the generator stuck
+    let mut zero = Prng::new(0);
+    assert_ne!(zero.next_u64(), 0);
+    for _ in 0..64 {
+        assert!(zero.below(7) < 7);
+    }
+}
+
+#[test_case]
+fn test_register_and_stack_flips() {
+    let (gid, pids) = start_group();
+    let frame = process::with_process(pids[1], |p| p.get_frame_addr()).unwrap() as *mut TrapFrame;
+    let stack = process::with_process(pids[2], |p| p.get_stack_addr()).unwrap() as *mut u8;
+
+    let reg = unsafe { (*frame).regs[10] };
+    let byte = unsafe { *stack.add(16) };
+
+    fault::init(Campaign {
+        faults: vec![
+            fault(gid, 1, Target::Register(10), 3),
+            fault(gid, 2, Target::Stack(16), 0),
+        ],
+        random: None,
+    });
+    fault::tick();
+
+    assert_eq!(unsafe { (*frame).regs[10] }, reg ^ (1 << 3));
+    assert_eq!(unsafe { *stack.add(16) }, byte ^ 1);
+    assert_eq!(fault::log().len(), 2);
+}
+
+#[test_case]
+fn test_result_flip_applies_once() {
+    let (gid, _) = start_group();
+
+    fault::init(Campaign {
+        faults: vec![fault(gid, 0, Target::Result, 2)],
+        random: None,
+    });
+    fault::tick();
+
+    assert_eq!(fault::corrupt_result(gid, 1, 4), 4);
+    assert_eq!(fault::corrupt_result(gid, 0, 4), 0);
+    assert_eq!(fault::corrupt_result(gid, 0, 4), 4);
+}
+
+#[test_case]
+fn test_report_vote_records_verdicts() {
+    let (gid, _) = start_group();
+
+    fault::init(Campaign {
+        faults: vec![
+            fault(gid, 1, Target::Result, 0),
+            fault(gid, 2, Target::Result, 0),
+        ],
+        random: None,
+    });
+    fault::tick();
+    fault::corrupt_result(gid, 1, 4);
+    fault::corrupt_result(gid, 2, 4);
+
+    fault::report_vote(
+        gid,
+        &Vote::Majority {
+            value: 4,
+            dissenters: vec![1],
+        },
+    );
+    let verdicts: Vec<_> = fault::log().iter().map(|i| i.verdict).collect();
+    assert_eq!(
+        verdicts,
+        vec![Some(Verdict::Detected), Some(Verdict::Masked)]
+    );
+
+    // faults are only reported to the first vote after them
+    fault::report_vote(gid, &Vote::NoMajority);
+    assert_eq!(fault::log()[0].verdict, Some(Verdict::Detected));
+}
+
+#[test_case]
+fn test_report_vote_matches_temporal_runs() {
+    let handle = process::create_replicated(common::task, 3, Mode::Temporal).unwrap();
+    let gid = handle.gid;
+
+    // the first run agrees, the fault lands in the second
+    replica::post(gid, 0, 4);
+    fault::init(Campaign {
+        faults: vec![fault(gid, 0, Target::Result, 0)],
+        random: None,
+    });
+    fault::tick();
+    fault::corrupt_result(gid, 0, 4);
+    assert_eq!(fault::log()[0].run, Some(1));
+
+    fault::report_vote(
+        gid,
+        &Vote::Majority {
+            value: 4,
+            dissenters: vec![1],
+        },
+    );
+    assert_eq!(fault::log()[0].verdict, Some(Verdict::Detected));
+}
+
+#[test_case]
+fn test_campaign_parse() {
+    let campaign =
+        Campaign::parse("fault=1:0:x10:3:100; fault=1:2:stack+0x40:7:0 random=9:1:5:10").unwrap();
+
+    assert_eq!(campaign.faults.len(), 2);
+    assert_eq!(campaign.faults[0].target, Target::Register(10));
+    assert_eq!(campaign.faults[0].cycle, 100);
+    assert_eq!(campaign.faults[1].target, Target::Stack(0x40));
+    assert_eq!(campaign.random.unwrap().count, 5);
+    assert!(Campaign::parse("").unwrap().faults.is_empty());
+
+    assert_eq!(
+        Campaign::parse("fault=1:0:x10:3").unwrap_err(),
+        CampaignError::FieldCount
+    );
+    assert_eq!(
+        Campaign::parse("fault=1:0:pc:3:0").unwrap_err(),
+        CampaignError::BadTarget
+    );
+    assert_eq!(
+        Campaign::parse("random=a:1:5:10").unwrap_err(),
+        CampaignError::BadNumber
+    );
+    assert_eq!(
+        Campaign::parse("faults=1").unwrap_err(),
+        CampaignError::UnknownItem
+    );
+}
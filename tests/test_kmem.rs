@@ -0,0 +1,172 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(strail::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use strail::arch::isa::page::{leaf_entry, PageEntryBits, Table, PAGE_SIZE};
+use strail::arch::kmem::{self, KmemError};
+
+#[cfg(test)]
+#[no_mangle]
+extern "C" fn main() {
+    strail::arch::mem::init();
+    kmem::init();
+    test_main();
+    strail::exit_qemu_as_success();
+}
+
+#[test_case]
+fn test_kfree_returns_memory() {
+    let free = kmem::free_bytes();
+
+    let ptr = kmem::kmalloc(64);
+    assert!(!ptr.is_null());
+    assert!(kmem::free_bytes() < free);
+
+    kmem::kfree(ptr);
+    assert_eq!(kmem::free_bytes(), free);
+}
+
+#[test_case]
+fn test_kfree_coalesces_neighbours() {
+    let free = kmem::free_bytes();
+
+    let first = kmem::kmalloc(128);
+    let second = kmem::kmalloc(128);
+    let third = kmem::kmalloc(128);
+
+    // freeing the middle one last merges it with both sides
+    kmem::kfree(first);
+    kmem::kfree(third);
+    kmem::kfree(second);
+    assert_eq!(kmem::free_bytes(), free);
+
+    // the merged chunk is reused for a larger request
+    let large = kmem::kmalloc(384);
+    assert_eq!(large, first);
+    kmem::kfree(large);
+}
+
+#[test_case]
+fn test_kfree_ignores_double_free() {
+    let free = kmem::free_bytes();
+
+    let ptr = kmem::kmalloc(32);
+    kmem::kfree(ptr);
+    kmem::kfree(ptr);
+    kmem::kfree(core::ptr::null_mut());
+    assert_eq!(kmem::free_bytes(), free);
+}
+
+#[test_case]
+fn test_kfree_ignores_pointers_into_an_allocation() {
+    let free = kmem::free_bytes();
+
+    let ptr = kmem::kmalloc(64);
+    let used = kmem::free_bytes();
+    kmem::kfree(unsafe { ptr.add(32) });
+    assert_eq!(kmem::free_bytes(), used);
+
+    kmem::kfree(ptr);
+    assert_eq!(kmem::free_bytes(), free);
+}
+
+#[test_case]
+fn test_heap_survives_repeated_allocations() {
+    let free = kmem::free_bytes();
+
+    for round in 0..1000 {
+        let v: Vec<usize> = (0..256).map(|i| i + round).collect();
+        assert_eq!(v[255], 255 + round);
+    }
+    assert_eq!(kmem::free_bytes(), free);
+}
+
+#[test_case]
+fn test_kmalloc_aligned_honours_alignment() {
+    let free = kmem::free_bytes();
+
+    for &align in [8, 16, 64, 256, 1024].iter() {
+        let ptr = kmem::kmalloc_aligned(24, align).unwrap();
+        assert_eq!(ptr as usize % align, 0);
+        kmem::kfree(ptr);
+    }
+    assert_eq!(kmem::free_bytes(), free);
+}
+
+#[test_case]
+fn test_kmalloc_aligned_uses_pages_for_page_alignment() {
+    let pages = strail::arch::mem::page_count();
+
+    let ptr = kmem::kmalloc_aligned(2 * PAGE_SIZE, PAGE_SIZE).unwrap();
+    assert_eq!(ptr as usize % PAGE_SIZE, 0);
+    assert_eq!(strail::arch::mem::page_count(), pages + 2);
+
+    kmem::kfree(ptr);
+    assert_eq!(strail::arch::mem::page_count(), pages);
+}
+
+#[test_case]
+fn test_kfree_ignores_pointers_not_starting_a_page_allocation() {
+    let pages = strail::arch::mem::page_count();
+    let ptr = kmem::kmalloc_aligned(2 * PAGE_SIZE, PAGE_SIZE).unwrap();
+    let stray = 0x8000_0000usize as *mut u8;
+
+    kmem::kfree(unsafe { ptr.add(8) });
+    kmem::kfree(unsafe { ptr.add(PAGE_SIZE) });
+    kmem::kfree(stray);
+    assert_eq!(strail::arch::mem::page_count(), pages + 2);
+
+    kmem::kfree(ptr);
+    kmem::kfree(ptr);
+    assert_eq!(strail::arch::mem::page_count(), pages);
+}
+
+#[test_case]
+fn test_kmalloc_aligned_rejects_bad_alignment() {
+    assert_eq!(
+        kmem::kmalloc_aligned(8, 24),
+        Err(KmemError::BadAlignment(24))
+    );
+    assert_eq!(
+        kmem::kmalloc_aligned(8, 2 * PAGE_SIZE),
+        Err(KmemError::UnsupportedAlignment(2 * PAGE_SIZE))
+    );
+}
+
+#[test_case]
+fn test_box_of_aligned_type_is_aligned() {
+    #[repr(align(64))]
+    struct Line([u8; 64]);
+
+    let line = Box::new(Line([1; 64]));
+    assert_eq!(&*line as *const Line as usize % 64, 0);
+    assert_eq!(line.0[63], 1);
+}
+
+#[test_case]
+fn test_kernel_map_is_enabled_and_sound() {
+    assert!(kmem::verify_kernel_map());
+    // the kernel page table is the one in use, in Sv39 mode
+    let satp = riscv::register::satp::read().bits();
+    assert_eq!(satp, kmem::satp());
+    assert_eq!(satp >> 60, 8);
+}
+
+#[test_case]
+fn test_kernel_text_is_not_writable() {
+    let root = unsafe { &*(((kmem::satp() & 0xff_ffff_ffff) << 12) as *const Table) };
+    let text = leaf_entry(root, unsafe { strail::consts::TEXT_START }).unwrap();
+    let rodata = leaf_entry(root, unsafe { strail::consts::RODATA_START }).unwrap();
+
+    assert_eq!(text & PageEntryBits::Write.val(), 0);
+    assert_ne!(text & PageEntryBits::Execute.val(), 0);
+    assert_eq!(rodata & PageEntryBits::Write.val(), 0);
+    assert_eq!(rodata & PageEntryBits::Execute.val(), 0);
+    assert_eq!(text & PageEntryBits::User.val(), 0);
+}
@@ -13,6 +13,7 @@ extern "C" fn main() {
     strail::exit_qemu_as_success();
 }
 
+use strail::arch::isa::page::PAGE_SIZE;
 use strail::arch::mem;
 
 #[cfg(any(target_arch = "arch::isa::page64", target_arch = "riscv32"))]
@@ -72,6 +73,55 @@ fn test_zalloc() {
     assert_eq!(mem::page_count(), 0);
 }
 
+#[test_case]
+fn test_alloc_counts_requested_pages() {
+    assert_eq!(mem::page_count(), 0);
+
+    let three = mem::alloc(3);
+    let five = mem::alloc(5);
+    assert_eq!(mem::page_count(), 8);
+    // blocks start on a multiple of their rounded up size
+    assert_eq!(
+        (three as usize).wrapping_sub(five as usize) % (4 * PAGE_SIZE),
+        0
+    );
+
+    mem::dealloc(three);
+    mem::dealloc(five);
+    assert_eq!(mem::page_count(), 0);
+}
+
+#[test_case]
+fn test_freed_pages_merge_back() {
+    let big = mem::alloc(1 << 12);
+    assert!(!big.is_null());
+    mem::dealloc(big);
+
+    let small: [*mut u8; 4] = [mem::alloc(1), mem::alloc(2), mem::alloc(1), mem::alloc(4)];
+    for &page in small.iter() {
+        mem::dealloc(page);
+    }
+
+    assert_eq!(mem::alloc(1 << 12), big);
+    mem::dealloc(big);
+    assert_eq!(mem::page_count(), 0);
+}
+
+#[test_case]
+fn test_only_first_page_is_an_allocation() {
+    let three = mem::alloc(3);
+
+    assert!(mem::is_allocation(three));
+    assert!(!mem::is_allocation(unsafe { three.add(PAGE_SIZE) }));
+    assert!(!mem::is_allocation(unsafe { three.add(8) }));
+    // below the allocatable pages
+    assert!(!mem::is_allocation(0x8000_0000usize as *mut u8));
+
+    mem::dealloc(three);
+    assert!(!mem::is_allocation(three));
+    assert_eq!(mem::page_count(), 0);
+}
+
 #[cfg(any(target_arch = "arch::isa::page64", target_arch = "riscv32"))]
 #[test_case]
 fn test_init_page() {
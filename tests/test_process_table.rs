@@ -0,0 +1,171 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(strail::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+mod common;
+
+use common::task;
+use strail::arch::isa::trap::TrapFrame;
+use strail::process::{self, ProcessTable};
+use strail::replica::{self, Mode};
+use strail::syscall::{self, Syscall};
+
+#[cfg(test)]
+#[no_mangle]
+extern "C" fn main() {
+    common::init();
+    test_main();
+    strail::exit_qemu_as_success();
+}
+
+/// Replaces the process table with an empty one
+fn reset() {
+    unsafe { process::PROCESS_TABLE = Some(ProcessTable::new()) };
+}
+
+fn table() -> &'static mut ProcessTable {
+    unsafe { process::PROCESS_TABLE.as_mut().unwrap() }
+}
+
+/// Makes `pid` issue `syscall` with `arg` through the syscall path, returns
+/// what the syscall left in a0
+fn call(pid: usize, syscall: Syscall, arg: usize) -> usize {
+    let frame = table().get(pid).unwrap().get_frame_addr() as *mut TrapFrame;
+
+    unsafe {
+        (*frame).set_arg(7, syscall as usize);
+        (*frame).set_arg(0, arg);
+        syscall::make_syscall((*frame).pc, frame);
+        (*frame).arg(0)
+    }
+}
+
+#[test_case]
+fn test_pids_start_at_one() {
+    reset();
+    let first = process::create_process(task);
+    let second = process::create_process(task);
+
+    assert_eq!((first, second), (1, 2));
+    assert_eq!(table().len(), 2);
+    assert_eq!(table().get(first).unwrap().get_pid(), first);
+    assert!(table().get(0).is_none());
+}
+
+#[test_case]
+fn test_freed_pids_are_reused_oldest_first() {
+    reset();
+    let pids = [
+        process::create_process(task),
+        process::create_process(task),
+        process::create_process(task),
+    ];
+
+    // without a parent, exited processes are reaped right away
+    process::exit(pids[1], 0);
+    process::exit(pids[0], 0);
+    assert_eq!(table().len(), 1);
+
+    assert_eq!(process::create_process(task), pids[1]);
+    assert_eq!(process::create_process(task), pids[0]);
+    assert_eq!(process::create_process(task), 4);
+}
+
+#[test_case]
+fn test_released_pid_is_reused() {
+    reset();
+    let pid = table().reserve();
+
+    table().release(pid);
+    table().release(pid);
+    assert_eq!(process::create_process(task), pid);
+    assert_eq!(process::create_process(task), pid + 1);
+
+    // a pid in use is not released
+    table().release(pid);
+    assert_eq!(process::create_process(task), pid + 2);
+}
+
+#[test_case]
+fn test_exited_replica_leaves_its_group() {
+    reset();
+    let handle = process::create_replicated(task, 3, Mode::Spatial).unwrap();
+    let members = replica::with_group(handle.gid, |g| g.live_members()).unwrap();
+
+    process::exit(members[1], 0);
+    assert_eq!(
+        replica::with_group(handle.gid, |g| g.replica_of(members[1])),
+        Some(None)
+    );
+
+    // the pid goes to a new process, which the group does not mistake for
+    // its replica
+    assert_eq!(process::create_process(task), members[1]);
+    assert_eq!(
+        replica::with_group(handle.gid, |g| g.live_members().len()),
+        Some(2)
+    );
+}
+
+#[test_case]
+fn test_reaped_process_keeps_its_usage() {
+    strail::stats::init();
+    reset();
+    let pid = process::create_process(task);
+
+    // what the trap handler does on a page fault
+    process::with_process(pid, |p| p.usage.page_faults += 1);
+    process::exit(pid, process::KILLED);
+    assert!(table().get(pid).is_none());
+
+    let exited = strail::stats::exited();
+    assert_eq!(exited.len(), 1);
+    assert_eq!(exited[0].pid, pid);
+    assert_eq!(exited[0].usage.page_faults, 1);
+}
+
+#[test_case]
+fn test_kernel_thread_is_reaped_once_its_entry_returns() {
+    reset();
+    let pid = strail::kthread::spawn(task);
+    let pages = strail::arch::mem::page_count();
+
+    // what the thread's trampoline does once `task` returns
+    process::exit(pid, 0);
+
+    assert!(table().get(pid).is_none());
+    assert!(strail::arch::mem::page_count() < pages);
+}
+
+#[test_case]
+fn test_process_without_memory_is_not_started() {
+    reset();
+    // more stack than the page allocator can hand out at once
+    let pid = process::start_kernel_thread(task as usize, 0, 1 << 20);
+
+    assert_eq!(pid, 0);
+    assert_eq!(table().len(), 0);
+    assert_eq!(process::create_process(task), 1);
+}
+
+#[test_case]
+fn test_processes_set_their_priority_and_deadline() {
+    reset();
+    let pid = process::create_process(task);
+
+    assert_eq!(call(pid, Syscall::SetPriority, 5), 0);
+    assert_eq!(table().get(pid).unwrap().priority, 5);
+    assert_eq!(call(pid, Syscall::SetDeadline, 10), 0);
+    assert!(table().get(pid).unwrap().deadline.is_some());
+    assert_eq!(call(pid, Syscall::SetDeadline, 0), 0);
+    assert_eq!(table().get(pid).unwrap().deadline, None);
+
+    // a replica's deadline belongs to its group's watchdog
+    let handle = process::create_replicated(task, 3, Mode::Spatial).unwrap();
+    let replica = replica::with_group(handle.gid, |g| g.members()[0]).unwrap();
+    assert_eq!(call(replica, Syscall::SetDeadline, 10), usize::MAX);
+}
@@ -0,0 +1,235 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(strail::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+mod common;
+
+use alloc::vec;
+use common::task;
+use strail::process::{self, State};
+use strail::replica::{self, Mode, Vote};
+use strail::stats;
+
+#[cfg(test)]
+#[no_mangle]
+extern "C" fn main() {
+    common::init();
+    test_main();
+    strail::exit_qemu_as_success();
+}
+
+#[test_case]
+fn test_vote_unanimous() {
+    assert_eq!(
+        replica::vote(&[Some(4), Some(4), Some(4)]),
+        Vote::Unanimous(4)
+    );
+}
+
+#[test_case]
+fn test_vote_majority() {
+    assert_eq!(
+        replica::vote(&[Some(4), Some(5), Some(4)]),
+        Vote::Majority {
+            value: 4,
+            dissenters: vec![1]
+        }
+    );
+}
+
+#[test_case]
+fn test_vote_missing_result_disagrees() {
+    assert_eq!(
+        replica::vote(&[None, Some(4), Some(4)]),
+        Vote::Majority {
+            value: 4,
+            dissenters: vec![0]
+        }
+    );
+}
+
+#[test_case]
+fn test_vote_no_majority() {
+    assert_eq!(
+        replica::vote(&[Some(1), Some(2), Some(3)]),
+        Vote::NoMajority
+    );
+    assert_eq!(replica::vote(&[Some(1), Some(2)]), Vote::NoMajority);
+    assert_eq!(replica::vote(&[None, None, Some(3)]), Vote::NoMajority);
+}
+
+#[test_case]
+fn test_groups_keep_their_results_apart() {
+    replica::init();
+    let first = replica::create_group(3, 0, Mode::Spatial).unwrap();
+    let second = replica::create_group(3, 0, Mode::Spatial).unwrap();
+
+    assert_eq!(replica::submit(first.gid, 0, 4), None);
+    assert_eq!(replica::submit(second.gid, 0, 7), None);
+    assert_eq!(replica::submit(first.gid, 1, 4), None);
+    assert_eq!(replica::submit(second.gid, 1, 7), None);
+    assert_eq!(replica::submit(first.gid, 2, 4), Some(Vote::Unanimous(4)));
+    assert_eq!(
+        replica::submit(second.gid, 2, 8),
+        Some(Vote::Majority {
+            value: 7,
+            dissenters: vec![2]
+        })
+    );
+}
+
+#[test_case]
+fn test_temporal_group_votes_over_runs() {
+    replica::init();
+    let group = replica::create_group(3, 0, Mode::Temporal).unwrap();
+
+    assert_eq!(group.replicas(), 1);
+    assert_eq!(replica::submit(group.gid, 0, 4), None);
+    assert_eq!(replica::submit(group.gid, 0, 5), None);
+    assert_eq!(
+        replica::submit(group.gid, 0, 4),
+        Some(Vote::Majority {
+            value: 4,
+            dissenters: vec![1]
+        })
+    );
+}
+
+#[test_case]
+fn test_posted_results_wait_for_the_voter() {
+    replica::init();
+    let group = replica::create_group(3, 0, Mode::Spatial).unwrap();
+
+    replica::post(group.gid, 0, 4);
+    replica::post(group.gid, 1, 4);
+    assert!(replica::vote_pending().is_empty());
+
+    replica::post(group.gid, 2, 4);
+    assert_eq!(
+        replica::vote_pending(),
+        vec![(group.gid, Vote::Unanimous(4))]
+    );
+    assert!(replica::vote_pending().is_empty());
+}
+
+#[test_case]
+fn test_signature_depends_on_order() {
+    let ab = replica::sign(replica::sign(0, 1), 2);
+    let ba = replica::sign(replica::sign(0, 2), 1);
+
+    assert_ne!(ab, ba);
+    assert_ne!(replica::sign(0, 1), replica::sign(replica::sign(0, 1), 1));
+}
+
+#[test_case]
+fn test_divergent_flow_is_flagged_when_results_agree() {
+    replica::init();
+    let group = replica::create_group(3, 0, Mode::Spatial).unwrap();
+
+    for id in 0..3 {
+        replica::sign_checkpoint(group.gid, id, 1);
+        if id != 1 {
+            replica::sign_checkpoint(group.gid, id, 2);
+        }
+    }
+
+    assert_eq!(replica::submit(group.gid, 0, 4), None);
+    assert_eq!(replica::submit(group.gid, 1, 4), None);
+    assert_eq!(
+        replica::submit(group.gid, 2, 4),
+        Some(Vote::Majority {
+            value: 4,
+            dissenters: vec![1]
+        })
+    );
+}
+
+#[test_case]
+fn test_stats_count_votes_and_disagreements() {
+    stats::init();
+    replica::init();
+    let group = replica::create_group(3, 0, Mode::Spatial).unwrap();
+
+    for value in [4, 5, 4].iter() {
+        replica::submit(group.gid, 0, 4);
+        replica::submit(group.gid, 1, *value);
+        replica::submit(group.gid, 2, 4);
+    }
+
+    let counters = stats::group(group.gid).unwrap();
+    assert_eq!(counters.votes, 3);
+    assert_eq!(counters.disagreements, 1);
+    assert_eq!(counters.no_majority, 0);
+    assert_eq!(counters.timeouts, 0);
+}
+
+#[test_case]
+fn test_finished_group_is_not_expired() {
+    stats::init();
+    replica::init();
+    let group = replica::create_group(3, 0, Mode::Spatial).unwrap();
+
+    for pid in 101..104 {
+        replica::join(group.gid, pid);
+    }
+    let deadline = replica::with_group(group.gid, |g| g.deadline()).unwrap();
+    assert_eq!(replica::with_group(group.gid, |g| g.is_armed()), Some(true));
+
+    replica::check_deadlines(deadline.wrapping_sub(1));
+    assert_eq!(stats::group(group.gid).unwrap().timeouts, 0);
+
+    for id in 0..3 {
+        replica::leave(group.gid, id);
+    }
+    assert_eq!(
+        replica::with_group(group.gid, |g| g.is_armed()),
+        Some(false)
+    );
+    replica::check_deadlines(deadline.wrapping_add(1_000_000));
+    assert_eq!(stats::group(group.gid).unwrap().timeouts, 0);
+}
+
+#[test_case]
+fn test_divergent_flow_without_majority_has_no_majority() {
+    replica::init();
+    let group = replica::create_group(2, 0, Mode::Spatial).unwrap();
+
+    replica::sign_checkpoint(group.gid, 0, 1);
+    replica::sign_checkpoint(group.gid, 1, 2);
+
+    assert_eq!(replica::submit(group.gid, 0, 4), None);
+    assert_eq!(replica::submit(group.gid, 1, 4), Some(Vote::NoMajority));
+}
+
+fn is_voting(pid: usize) -> bool {
+    process::with_process(pid, |p| matches!(p.get_state(), State::Voting)).unwrap()
+}
+
+#[test_case]
+fn test_posting_replica_waits_for_the_vote() {
+    replica::init();
+    let handle = process::create_replicated(task, 3, Mode::Spatial).unwrap();
+    let pids = replica::with_group(handle.gid, |g| g.live_members()).unwrap();
+
+    replica::post(handle.gid, 0, 4);
+    assert!(is_voting(pids[0]));
+    assert!(!is_voting(pids[1]));
+
+    replica::post(handle.gid, 1, 4);
+    replica::post(handle.gid, 2, 5);
+    assert_eq!(
+        replica::vote_pending(),
+        vec![(
+            handle.gid,
+            Vote::Majority {
+                value: 4,
+                dissenters: vec![2]
+            }
+        )]
+    );
+    assert!(pids.iter().all(|&pid| !is_voting(pid)));
+}
@@ -0,0 +1,79 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(strail::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use strail::sched::{Edf, Priority, RoundRobin, Scheduler, Task};
+
+#[cfg(test)]
+#[no_mangle]
+extern "C" fn main() {
+    strail::arch::mem::init();
+    strail::arch::kmem::init();
+    test_main();
+    strail::exit_qemu_as_success();
+}
+
+fn task(pid: usize, priority: usize, deadline: Option<u64>) -> Task {
+    Task {
+        pid,
+        priority,
+        deadline,
+    }
+}
+
+#[test_case]
+fn test_round_robin_takes_turns() {
+    let mut sched = RoundRobin::new();
+    sched.enqueue(task(1, 0, None));
+    sched.enqueue(task(2, 0, None));
+    sched.enqueue(task(3, 0, None));
+
+    assert_eq!(sched.pick_next(&|_| true), Some(1));
+    assert_eq!(sched.pick_next(&|_| true), Some(2));
+    assert_eq!(sched.pick_next(&|pid| pid != 3), Some(1));
+    assert_eq!(sched.pick_next(&|_| false), None);
+}
+
+#[test_case]
+fn test_dequeued_task_is_not_picked() {
+    let mut sched = RoundRobin::new();
+    sched.enqueue(task(1, 0, None));
+    sched.enqueue(task(2, 0, None));
+
+    assert_eq!(sched.dequeue(1), Some(task(1, 0, None)));
+    assert_eq!(sched.dequeue(1), None);
+    assert_eq!(sched.pick_next(&|_| true), Some(2));
+    assert_eq!(sched.pick_next(&|_| true), Some(2));
+}
+
+#[test_case]
+fn test_priority_runs_highest_ready_first() {
+    let mut sched = Priority::new();
+    sched.enqueue(task(1, 1, None));
+    sched.enqueue(task(2, 5, None));
+    sched.enqueue(task(3, 5, None));
+
+    assert_eq!(sched.pick_next(&|_| true), Some(2));
+    assert_eq!(sched.pick_next(&|_| true), Some(3));
+    assert_eq!(sched.pick_next(&|_| true), Some(2));
+    assert_eq!(sched.pick_next(&|pid| pid == 1), Some(1));
+}
+
+#[test_case]
+fn test_edf_runs_earliest_deadline_first() {
+    let mut sched = Edf::new();
+    sched.enqueue(task(1, 0, None));
+    sched.enqueue(task(2, 0, Some(300)));
+    sched.enqueue(task(3, 0, Some(100)));
+
+    assert_eq!(sched.pick_next(&|_| true), Some(3));
+    assert_eq!(sched.pick_next(&|pid| pid != 3), Some(2));
+    assert_eq!(sched.pick_next(&|pid| pid == 1), Some(1));
+
+    sched.tick(200);
+    assert_eq!(sched.misses(), 1);
+    sched.tick(250);
+    assert_eq!(sched.misses(), 1);
+}
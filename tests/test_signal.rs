@@ -0,0 +1,145 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(strail::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+mod common;
+
+use common::task;
+use strail::arch::isa::trap::TrapFrame;
+use strail::process::{self, State, Wait};
+use strail::replica::Mode;
+use strail::signal::{self, Signal};
+use strail::syscall::{self, Syscall};
+
+#[cfg(test)]
+#[no_mangle]
+extern "C" fn main() {
+    common::init();
+    test_main();
+    strail::exit_qemu_as_success();
+}
+
+fn handler(_signal: usize) {}
+
+fn frame(pid: usize) -> &'static mut TrapFrame {
+    let addr = process::with_process(pid, |p| p.get_frame_addr()).unwrap();
+    unsafe { &mut *(addr as *mut TrapFrame) }
+}
+
+/// Makes `pid` call `kill(target, signal)` through the syscall path
+fn call_kill(pid: usize, target: usize, signal: Signal) {
+    let frame = frame(pid);
+    let pc = frame.pc;
+
+    frame.set_arg(7, Syscall::Kill as usize);
+    frame.set_arg(0, target);
+    frame.set_arg(1, signal as usize);
+    unsafe { syscall::make_syscall(pc, frame) };
+}
+
+#[test_case]
+fn test_self_signal_runs_handler_and_returns_zero() {
+    let pid = process::create_process(task);
+    process::with_process(pid, |p| p.set_handler(handler as usize));
+    let pc = frame(pid).pc;
+
+    call_kill(pid, pid, Signal::User);
+    assert_eq!(frame(pid).arg(0), handler as usize);
+    assert_eq!(frame(pid).arg(1), Signal::User as usize);
+
+    assert!(signal::sigreturn(pid));
+    assert_eq!(frame(pid).arg(0), 0);
+    assert_eq!(frame(pid).pc, pc + 4);
+}
+
+#[test_case]
+fn test_signal_to_waiting_process_survives_wakeup() {
+    let parent = process::create_process(task);
+    let child = process::spawn(parent, task as usize, 0, 0).unwrap();
+    process::with_process(parent, |p| p.set_handler(handler as usize));
+
+    assert_eq!(process::wait(parent, child), Wait::Blocked);
+    assert!(signal::kill(parent, Signal::User));
+    process::exit(child, 3);
+
+    // the handler runs first, wait returns the status once it is done
+    assert_eq!(frame(parent).arg(0), handler as usize);
+    assert!(signal::sigreturn(parent));
+    assert_eq!(frame(parent).arg(0), 3);
+}
+
+#[test_case]
+fn test_terminate_reaps_orphans_and_spares_kernel_threads() {
+    let pid = process::create_process(task);
+    call_kill(pid, pid, Signal::Terminate);
+    assert!(process::with_process(pid, |_| ()).is_none());
+    assert!(!signal::kill(pid, Signal::Terminate));
+
+    let parent = process::create_process(task);
+    let child = process::spawn(parent, task as usize, 0, 0).unwrap();
+    assert!(signal::kill(child, Signal::Terminate));
+    assert_eq!(process::wait(parent, child), Wait::Exited(process::KILLED));
+
+    let kthread = process::start_kernel_thread(task as usize, 0, 1);
+    assert!(!signal::kill(kthread, Signal::Terminate));
+    assert!(process::with_process(kthread, |p| !p.is_zombie()).unwrap());
+}
+
+fn state(pid: usize) -> State {
+    process::with_process(pid, |p| p.get_state().clone()).unwrap()
+}
+
+fn is_stopped(pid: usize) -> bool {
+    process::with_process(pid, |p| p.is_stopped()).unwrap()
+}
+
+#[test_case]
+fn test_stop_and_continue_keep_a_sleeper_asleep() {
+    let pid = process::create_process(task);
+    let until = strail::sched::get_mtime().wrapping_add(1 << 40);
+    process::with_process(pid, |p| p.sleep(until));
+
+    assert!(signal::kill(pid, Signal::Stop));
+    assert!(is_stopped(pid));
+    assert!(signal::kill(pid, Signal::Continue));
+
+    assert!(!is_stopped(pid));
+    assert!(matches!(state(pid), State::Sleeping));
+}
+
+#[test_case]
+fn test_stopped_waiter_stays_stopped_when_its_child_exits() {
+    let parent = process::create_process(task);
+    let child = process::spawn(parent, task as usize, 0, 0).unwrap();
+
+    assert_eq!(process::wait(parent, child), Wait::Blocked);
+    signal::kill(parent, Signal::Stop);
+    signal::kill(parent, Signal::Continue);
+    assert!(matches!(state(parent), State::Waiting));
+
+    signal::kill(parent, Signal::Stop);
+    process::exit(child, 3);
+    assert!(matches!(state(parent), State::Running));
+    assert!(is_stopped(parent));
+    assert_eq!(frame(parent).arg(0), 3);
+
+    signal::kill(parent, Signal::Continue);
+    assert!(!is_stopped(parent));
+}
+
+#[test_case]
+fn test_stop_and_continue_keep_a_replica_waiting_for_its_vote() {
+    strail::replica::init();
+    let handle = process::create_replicated(task, 3, Mode::Spatial).unwrap();
+    let pid = strail::replica::with_group(handle.gid, |g| g.live_members()[0]).unwrap();
+
+    strail::replica::post(handle.gid, 0, 4);
+    signal::kill(pid, Signal::Stop);
+    signal::kill(pid, Signal::Continue);
+
+    assert!(matches!(state(pid), State::Voting));
+}
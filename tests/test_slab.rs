@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(strail::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use strail::arch::isa::page::{Table, PAGE_SIZE};
+use strail::arch::isa::trap::TrapFrame;
+use strail::arch::mem;
+use strail::slab::{self, Cache, Cached};
+
+#[cfg(test)]
+#[no_mangle]
+extern "C" fn main() {
+    mem::init();
+    strail::arch::kmem::init();
+    test_main();
+    strail::exit_qemu_as_success();
+}
+
+#[test_case]
+fn test_frames_share_a_page() {
+    let pages = mem::page_count();
+    let frames: [*mut TrapFrame; 4] = [
+        slab::zalloc(),
+        slab::zalloc(),
+        slab::zalloc(),
+        slab::zalloc(),
+    ];
+
+    assert!(PAGE_SIZE / Cache::<TrapFrame>::object_size() >= 4);
+    assert!(mem::page_count() <= pages + 1);
+    for &frame in frames.iter() {
+        assert!(!frame.is_null());
+        unsafe { assert_eq!((*frame).pc, 0) };
+        slab::free(frame);
+    }
+}
+
+#[test_case]
+fn test_freed_object_is_reused() {
+    let table: *mut Table = slab::zalloc();
+    slab::free(table);
+
+    let again: *mut Table = slab::zalloc();
+    assert_eq!(again, table);
+    assert_eq!(again as usize % PAGE_SIZE, 0);
+    slab::free(again);
+}
+
+#[test_case]
+fn test_cache_counts_usage() {
+    let before = TrapFrame::cache().stats();
+
+    let first: *mut TrapFrame = slab::zalloc();
+    let second: *mut TrapFrame = slab::zalloc();
+    slab::free(first);
+
+    let stats = TrapFrame::cache().stats();
+    assert_eq!(stats.allocs, before.allocs + 2);
+    assert_eq!(stats.frees, before.frees + 1);
+    assert_eq!(stats.in_use, before.in_use + 1);
+    assert!(stats.peak >= before.in_use + 2);
+
+    slab::free(second);
+}